@@ -0,0 +1,5041 @@
+// Editor core: buffers, cursors, editing and commands, independent of the terminal.
+// The front end feeds it keys and reads back the buffer and cursors to draw them.
+
+use std::cmp::{max, min};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::hash_map::DefaultHasher;
+use std::convert::TryFrom;
+use std::fmt::{self, Write as _};
+use std::hash::{Hash, Hasher};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local, TimeZone};
+
+use ropey::Rope;
+use ropey::RopeSlice;
+
+use unicode_width::UnicodeWidthChar;
+
+// Index of the last line: a buffer ending with a line break has an empty
+// last line after it, which is where the cursor lands on Down or End
+pub fn lines(rope: &Rope) -> usize {
+    rope.len_lines().saturating_sub(1)
+}
+
+// Line breaks as recognized by Rope, which ends a line on any of them
+pub fn is_break(c: char) -> bool {
+    matches!(c, '\n' | '\u{0B}' | '\u{0C}' | '\r' | '\u{85}' | '\u{2028}' | '\u{2029}')
+}
+
+// Number of columns of a line, excluding its line break
+pub fn columns(line: RopeSlice) -> usize {
+    let max = line.len_chars();
+    if max > 1 && line.char(max - 1) == '\n' && line.char(max - 2) == '\r' { max - 2 }
+    else if max > 0 && is_break(line.char(max - 1)) { max - 1 } else { max }
+}
+
+// Position after the last character, the only one a cursor reaches past the last line break
+pub fn end(rope: &Rope) -> usize {
+    let line = lines(rope);
+    rope.line_to_char(line) + columns(rope.line(line))
+}
+
+// Number of terminal cells used to display a character
+pub fn width(c: char) -> usize {
+    c.width().unwrap_or(1)
+}
+
+// Cells taken by `c` drawn at cell `cell`, a tab reaching up to the next tab stop
+pub fn advance(c: char, cell: usize, tab_width: usize) -> usize {
+    if c == '\t' { tab_width.max(1) - cell % tab_width.max(1) } else { width(c) }
+}
+
+// Cells taken by `line` drawn from cell `start`, at which tab stops are counted from 0.
+// Chunks of ASCII without tabs or NULs take a cell a byte and are not read a character at
+// a time, so that counting up to the view on a long line stays quick.
+pub fn cells(line: RopeSlice, start: usize, tab_width: usize) -> usize {
+    let mut used = start;
+    for chunk in line.chunks() {
+        if chunk.is_ascii() && !chunk.contains('\t') && !chunk.contains('\0') {
+            used += chunk.len();
+        } else {
+            used = chunk.chars().fold(used, |used, c| used + advance(c, used, tab_width));
+        }
+    }
+    used - start
+}
+
+// Number of leading characters of `line` drawn from cell `start` that fit within `cells`
+// terminal cells
+pub fn fit(line: RopeSlice, start: usize, cells: usize, tab_width: usize) -> usize {
+    let mut used = start;
+    line.chars().take_while(|&c| { used += advance(c, used, tab_width); used - start <= cells }).count()
+}
+
+pub fn is_path_char(c: char) -> bool {
+    c.is_alphanumeric() || "/._-~+@%".contains(c)
+}
+
+// Character of a code point in hex, with or without the U+ in front of it
+pub fn code_point(hex: &str) -> Result<char, String> {
+    let digits = hex.trim();
+    let digits = digits.strip_prefix("U+").or_else(|| digits.strip_prefix("u+")).unwrap_or(digits);
+    match u32::from_str_radix(digits, 16) {
+        Ok(n) => char::from_u32(n).ok_or_else(|| format!("Not a character: U+{:04X}", n)),
+        Err(_) => Err(format!("Invalid code point: {}", hex)),
+    }
+}
+
+// Bracket closing an opening one
+pub fn closing(open: char) -> char {
+    match open {
+        '{' => '}',
+        '[' => ']',
+        '(' => ')',
+        c => c,
+    }
+}
+
+// Tokens starting a line comment and delimiting a block comment, either being missing when
+// a language has no such comments
+pub type Comments = (Option<&'static str>, Option<(&'static str, &'static str)>);
+
+// Comment tokens of the language of a file extension
+pub fn comments(extension: &str) -> Comments {
+    match extension {
+        "c" | "h" | "cc" | "cpp" | "hpp" | "cs" | "d" | "go" | "java" | "js" | "jsx" | "kt" | "rs" | "scala" | "scss"
+        | "swift" | "ts" | "tsx" | "zig" => (Some("//"), Some(("/*", "*/"))),
+        "css" => (None, Some(("/*", "*/"))),
+        "html" | "md" | "svg" | "xml" => (None, Some(("<!--", "-->"))),
+        "sh" | "bash" | "zsh" | "fish" | "py" | "rb" | "pl" | "r" | "toml" | "yaml" | "yml" | "conf" | "ini" | "mk"
+        | "cmake" | "nix" | "tedrc" => (Some("#"), None),
+        "lua" => (Some("--"), Some(("--[[", "]]"))),
+        "hs" | "sql" => (Some("--"), Some(("{-", "-}"))),
+        "el" | "lisp" | "clj" | "scm" | "asm" => (Some(";"), None),
+        "tex" | "erl" => (Some("%"), None),
+        "vim" => (Some("\""), None),
+        _ => (None, None),
+    }
+}
+
+// Position of the bracket matching the one at `pos`, skipping the pairs of the same
+// brackets nested in between
+pub fn matching(rope: &Rope, pos: usize) -> Option<usize> {
+    if pos >= rope.len_chars() {
+        return None;
+    }
+    let c = rope.char(pos);
+    let (open, close) = match c {
+        '(' | '[' | '{' => (c, closing(c)),
+        ')' => ('(', c),
+        ']' => ('[', c),
+        '}' => ('{', c),
+        _ => return None,
+    };
+    let mut depth = 0;
+    if c == open {
+        for (i, d) in rope.chars_at(pos).enumerate() {
+            if d == open { depth += 1; }
+            if d == close { depth -= 1; }
+            if depth == 0 { return Some(pos + i); }
+        }
+    } else {
+        let mut chars = rope.chars_at(pos + 1);
+        let mut i = pos + 1;
+        while let Some(d) = chars.prev() {
+            i -= 1;
+            if d == close { depth += 1; }
+            if d == open { depth -= 1; }
+            if depth == 0 { return Some(i); }
+        }
+    }
+    None
+}
+
+// Offset of the first bracket of `text` left unmatched, a closing one not matching the last
+// one open, or the first of those left open at the end, and the closing brackets of those,
+// innermost first. With the comment tokens of a language, the brackets in its comments, in
+// strings within a line and in characters in single quotes are not counted, otherwise all
+// of them are.
+pub fn unbalanced(text: &str, language: Option<Comments>) -> Option<(usize, Vec<char>)> {
+    let chars = text.chars().collect::<Vec<char>>();
+    let at = |i: usize, token: &str| token.chars().enumerate().all(|(k, c)| chars.get(i + k) == Some(&c));
+    let (line, block) = language.unwrap_or((None, None));
+    let mut open = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if let Some(token) = line.filter(|token| at(i, token)) {
+            i += token.chars().count();
+            while i < chars.len() && chars[i] != '\n' { i += 1; }
+            continue;
+        }
+        if let Some((start, end)) = block.filter(|(start, _)| at(i, start)) {
+            i += start.chars().count();
+            while i < chars.len() && !at(i, end) { i += 1; }
+            i += end.chars().count();
+            continue;
+        }
+        if c == '"' && language.is_some() {
+            let mut j = i + 1;
+            while j < chars.len() && chars[j] != '"' && chars[j] != '\n' {
+                j += if chars[j] == '\\' { 2 } else { 1 };
+            }
+            // A quote not closed on its line is not taken for a string
+            if j < chars.len() && chars[j] == '"' {
+                i = j + 1;
+                continue;
+            }
+        }
+        if c == '\'' && language.is_some() {
+            let len = if chars.get(i + 1) == Some(&'\\') { 2 } else { 1 };
+            if chars.get(i + 1).is_some_and(|&c| c != '\n') && chars.get(i + 1 + len) == Some(&'\'') {
+                i += len + 2;
+                continue;
+            }
+        }
+        match c {
+            '(' | '[' | '{' => open.push((c, i)),
+            ')' | ']' | '}' => match open.pop() {
+                Some((bracket, _)) if closing(bracket) == c => {}
+                _ => return Some((i, Vec::new())),
+            },
+            _ => {}
+        }
+        i += 1;
+    }
+    let first = open.first()?.1;
+    Some((first, open.iter().rev().map(|&(bracket, _)| closing(bracket)).collect()))
+}
+
+// Positions of the innermost pair of brackets around `pos`, or of the one it is on
+pub fn enclosing(rope: &Rope, pos: usize) -> Option<(usize, usize)> {
+    if let Some(other) = matching(rope, pos) {
+        return Some((min(pos, other), max(pos, other)));
+    }
+    let mut chars = rope.chars_at(pos);
+    let mut i = pos;
+    while let Some(c) = chars.prev() {
+        i -= 1;
+        if "([{".contains(c) {
+            if let Some(close) = matching(rope, i).filter(|&close| close >= pos) {
+                return Some((i, close));
+            }
+        } else if ")]}".contains(c) {
+            // A pair closed before `pos` is skipped as a whole
+            if let Some(open) = matching(rope, i) {
+                while i > open {
+                    chars.prev();
+                    i -= 1;
+                }
+            }
+        }
+    }
+    None
+}
+
+pub fn indentation(line: RopeSlice) -> String {
+    line.chars().take_while(|&c| c == ' ' || c == '\t').collect()
+}
+
+// Number of spaces before `col` to remove to reach the previous tab stop,
+// or 1 when `col` is not within leading spaces
+pub fn soft_tab(line: RopeSlice, col: usize, tab_width: usize) -> usize {
+    if col == 0 || tab_width == 0 || line.slice(..col).chars().any(|c| c != ' ') {
+        1
+    } else {
+        col - (col - 1) / tab_width * tab_width
+    }
+}
+
+// Number of times `needle` occurs in the rope, reading it a character at a time so that
+// nothing the size of the buffer is allocated. Occurrences do not overlap: like with
+// `str::matches`, each one is searched for after the end of the previous one.
+pub fn count_matches(rope: &Rope, needle: &str, case_insensitive: bool) -> usize {
+    let needle = needle.chars().collect::<Vec<char>>();
+    if needle.is_empty() {
+        return 0;
+    }
+    let same = |a: char, b: char| a == b || case_insensitive && a.to_lowercase().eq(b.to_lowercase());
+
+    // The last characters read, as many as in the needle once enough were
+    let mut window = VecDeque::with_capacity(needle.len());
+    let mut count = 0;
+    for c in rope.chars() {
+        if window.len() == needle.len() {
+            window.pop_front();
+        }
+        window.push_back(c);
+        if window.len() == needle.len() && window.iter().zip(&needle).all(|(&a, &b)| same(a, b)) {
+            count += 1;
+            window.clear();
+        }
+    }
+    count
+}
+
+// Columns where `word` occurs in `text` as a whole word
+pub fn occurrences<P: Fn(char) -> bool>(text: &str, word: &str, is_word: P) -> Vec<usize> {
+    let chars = text.chars().collect::<Vec<char>>();
+    let word = word.chars().collect::<Vec<char>>();
+    if word.is_empty() || word.len() > chars.len() {
+        return Vec::new();
+    }
+    (0..=chars.len() - word.len()).filter(|&i| {
+        chars[i..i + word.len()] == word[..]
+            && (i == 0 || !is_word(chars[i - 1]))
+            && chars.get(i + word.len()).is_none_or(|&c| !is_word(c))
+    }).collect()
+}
+
+// Breaks text into lines of at most `limit` cells, each starting with `indent`. Words are
+// kept whole unless `greedy`, which fills every line up to the limit. Lines never end or,
+// past the first, start with spaces. Words longer than a line get a line of their own.
+pub fn wrap(text: &str, indent: &str, limit: usize, greedy: bool) -> Vec<String> {
+    let margin = indent.chars().map(width).sum::<usize>();
+    let mut lines = Vec::new();
+    let mut line = indent.to_string();
+    let mut cells = margin;
+    if greedy {
+        for c in text.chars() {
+            if cells + width(c) > limit && cells > margin {
+                lines.push(line.trim_end().to_string());
+                line = indent.to_string();
+                cells = margin;
+            }
+            if cells > margin || !c.is_whitespace() {
+                line.push(c);
+                cells += width(c);
+            }
+        }
+    } else {
+        for word in text.split_whitespace() {
+            let cost = word.chars().map(width).sum::<usize>();
+            if cells > margin && cells + 1 + cost > limit {
+                lines.push(line);
+                line = indent.to_string();
+                cells = margin;
+            }
+            if cells > margin {
+                line.push(' ');
+                cells += 1;
+            }
+            line.push_str(word);
+            cells += cost;
+        }
+    }
+    lines.push(line.trim_end().to_string());
+    lines
+}
+
+// Length of the escape sequence at the start of `chars`, an ESC, if it is one terminals take:
+// a control sequence, a control string ended by ST, or BEL for OSC, a character set chosen,
+// a character of another set, as sent for function keys, or one of the sequences of a single
+// character
+fn escape(chars: &[char]) -> Option<usize> {
+    match *chars.get(1)? {
+        '[' => {
+            let mut i = 2;
+            while matches!(chars.get(i), Some('\x30'..='\x3f')) { i += 1; }
+            while matches!(chars.get(i), Some('\x20'..='\x2f')) { i += 1; }
+            match chars.get(i)? {
+                '\x40'..='\x7e' => Some(i + 1),
+                _ => None,
+            }
+        }
+        c @ (']' | 'P' | 'X' | '^' | '_') => {
+            for i in 2..chars.len() {
+                match chars[i] {
+                    '\x07' if c == ']' => return Some(i + 1),
+                    '\x1b' if chars.get(i + 1) == Some(&'\\') => return Some(i + 2),
+                    '\x1b' => return None,
+                    _ => {}
+                }
+            }
+            None
+        }
+        '(' | ')' | '*' | '+' | 'N' | 'O' => match chars.get(2)? {
+            '\x30'..='\x7e' => Some(3),
+            _ => None,
+        },
+        '\x40'..='\x5f' | '7' | '8' | '=' | '>' | 'c' => Some(2),
+        _ => None,
+    }
+}
+
+// Text without its escape sequences, like those coloring the output of commands, and how
+// many there were. An ESC starting none of them is kept.
+pub fn strip_escapes(text: &str) -> (String, usize) {
+    let chars = text.chars().collect::<Vec<char>>();
+    let mut stripped = String::with_capacity(text.len());
+    let mut count = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        match escape(&chars[i..]).filter(|_| chars[i] == '\x1b') {
+            Some(len) => {
+                i += len;
+                count += 1;
+            }
+            None => {
+                stripped.push(chars[i]);
+                i += 1;
+            }
+        }
+    }
+    (stripped, count)
+}
+
+// Keys as the editor sees them, whatever reads them from the terminal
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Key {
+    Backspace,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    BackTab,
+    Delete,
+    Insert,
+    F(u8),
+    Char(char),
+    Alt(char),
+    Ctrl(char),
+    Null,
+    Esc,
+    // Movement keys held with Shift, which select
+    Shift(Nav),
+    // Movement keys held with Ctrl or Alt, and with Shift along with either
+    CtrlNav(Nav),
+    AltNav(Nav),
+    CtrlShift(Nav),
+    AltShift(Nav),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Nav {
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+}
+
+impl From<Nav> for Key {
+    fn from(nav: Nav) -> Self {
+        match nav {
+            Nav::Left => Key::Left,
+            Nav::Right => Key::Right,
+            Nav::Up => Key::Up,
+            Nav::Down => Key::Down,
+            Nav::Home => Key::Home,
+            Nav::End => Key::End,
+            Nav::PageUp => Key::PageUp,
+            Nav::PageDown => Key::PageDown,
+        }
+    }
+}
+
+pub struct Cursor {
+    line: usize,
+    col: usize,
+    // Line and column where the selection started, the other end being the cursor
+    pub anchor: Option<(usize, usize)>,
+    // Kept when going back to a single cursor, and not moved along with the others
+    pub pinned: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Movement {
+    Up(usize),
+    Down(usize),
+    Left(usize),
+    Right(usize),
+    Begin,
+    End,
+    LineBegin,
+    LineEnd,
+    Goto(usize, usize),
+    GotoLine(usize),
+    GotoCol(usize),
+}
+
+impl Cursor {
+    pub fn new(line: usize, col: usize) -> Self {
+        Self { line, col, anchor: None, pinned: false }
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn columns(&self, rope: &Rope) -> usize {
+        columns(rope.line(self.line))
+    }
+
+    pub fn col(&self, rope: &Rope) -> usize {
+        min(self.col, self.columns(rope))
+    }
+
+    pub fn pos(&self, rope: &Rope) -> usize {
+        rope.line_to_char(self.line) + self.col(rope)
+    }
+
+    // Positions between the anchor and the cursor, if there is a selection
+    pub fn selection(&self, rope: &Rope) -> Option<(usize, usize)> {
+        let (line, col) = self.anchor?;
+        let line = min(line, lines(rope));
+        let anchor = rope.line_to_char(line) + min(col, columns(rope.line(line)));
+        let pos = self.pos(rope);
+        Some((min(anchor, pos), anchor.max(pos)))
+    }
+
+    // First and last lines of the selection, or the cursor's line without one. A selection
+    // ending at the start of a line leaves that line out.
+    pub fn lines(&self, rope: &Rope) -> (usize, usize) {
+        match self.selection(rope) {
+            Some((start, end)) => {
+                let (first, last) = (rope.char_to_line(start), rope.char_to_line(end));
+                (first, if last > first && end == rope.line_to_char(last) { last - 1 } else { last })
+            }
+            None => (self.line, self.line),
+        }
+    }
+
+    // Removes the selected text in one go, whichever end the cursor is at, and moves
+    // the cursor to where it started. Returns false if nothing is selected.
+    fn remove_selection(&mut self, rope: &mut Rope) -> bool {
+        match self.selection(rope) {
+            Some((start, end)) if start < end => {
+                rope.remove(start..end);
+                let line = rope.char_to_line(start);
+                self.apply(rope, Movement::Goto(line, start - rope.line_to_char(line)));
+                self.anchor = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn apply(&mut self, rope: &Rope, movement: Movement) {
+        match movement {
+            Movement::Up(n) => {
+                self.line = self.line.saturating_sub(n);
+            }
+            Movement::Down(n) => {
+                self.line = if self.line + n >= lines(rope) { lines(rope) } else { self.line + n };
+            }
+            Movement::Left(n) => {
+                self.col = self.col(rope);
+                for _ in 0..n {
+                    if self.col > 0 {
+                        self.col -= 1;
+                    } else if self.line > 0 {
+                        self.line -= 1;
+                        self.col = self.columns(rope);
+                    } else {
+                        break
+                    }
+                }
+            }
+            Movement::Right(n) => {
+                self.col = self.col(rope);
+                for _ in 0..n {
+                    if self.col < self.columns(rope) {
+                        self.col += 1;
+                    } else if self.line < lines(rope) {
+                        self.line += 1;
+                        self.col = 0;
+                    } else {
+                        break
+                    }
+                }
+            }
+            Movement::LineBegin => {
+                if self.col == 0 { self.apply(rope, Movement::Up(1)) }
+                else {  self.col = 0 }
+            }
+            Movement::LineEnd => {
+                if self.col >= self.columns(rope) {
+                    self.apply(rope, Movement::Down(1))
+                }
+                self.col = self.columns(rope);
+            }
+            Movement::Begin => {
+                self.line = 0;
+                self.col = 0;
+            }
+            Movement::End => {
+                self.line = lines(rope);
+                self.col = self.columns(rope);
+            }
+            Movement::Goto(line, col) => {
+                self.line = min(line, lines(rope));
+                self.col = min(col, self.columns(rope));
+            }
+            Movement::GotoLine(line) => {
+                self.line = min(line, lines(rope));
+            }
+            Movement::GotoCol(col) => {
+                self.col = min(col, self.columns(rope));
+            }
+        }
+    }
+}
+
+pub const MESSAGE_TIMEOUT: Duration = Duration::from_secs(4);
+pub const FLASH_TIMEOUT: Duration = Duration::from_millis(400);
+// Time the cursor rests on a word before its occurrences are marked
+pub const HIGHLIGHT_DELAY: Duration = Duration::from_millis(300);
+// Interval at which a running git diff is checked for its result
+pub const DIFF_POLL: Duration = Duration::from_millis(50);
+// Interval at which the progress of a save on its own thread is shown
+pub const SAVE_POLL: Duration = Duration::from_millis(100);
+// Size from which buffers are saved on a thread of their own, while they are read-only
+pub const BACKGROUND_SAVE: usize = 8 << 20;
+
+// Encodings files are read and saved in, the text being edited as UTF-8
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Latin1,
+    Windows1252,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Latin1 => "latin1",
+            Encoding::Windows1252 => "windows-1252",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "latin1" | "latin-1" | "iso-8859-1" => Some(Encoding::Latin1),
+            "windows-1252" | "cp1252" => Some(Encoding::Windows1252),
+            "utf-16le" | "utf-16" => Some(Encoding::Utf16Le),
+            "utf-16be" => Some(Encoding::Utf16Be),
+            _ => None,
+        }
+    }
+
+    // UTF-16 is only told by its byte order mark, and bytes that are not UTF-8 are
+    // taken as Windows-1252, which any bytes are
+    pub fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(Encoding::Utf16Le.bom()) {
+            Encoding::Utf16Le
+        } else if bytes.starts_with(Encoding::Utf16Be.bom()) {
+            Encoding::Utf16Be
+        } else if std::str::from_utf8(bytes).is_ok() {
+            Encoding::Utf8
+        } else {
+            Encoding::Windows1252
+        }
+    }
+
+    // Byte order mark written at the start of files, and left out of the text
+    fn bom(self) -> &'static [u8] {
+        match self {
+            Encoding::Utf16Le => &[0xFF, 0xFE],
+            Encoding::Utf16Be => &[0xFE, 0xFF],
+            _ => &[],
+        }
+    }
+
+    // Text of the bytes, and whether some of them were not valid and were replaced
+    pub fn decode(self, bytes: &[u8]) -> (String, bool) {
+        let bytes = bytes.strip_prefix(self.bom()).unwrap_or(bytes);
+        match self {
+            Encoding::Utf8 => match std::str::from_utf8(bytes) {
+                Ok(text) => (text.to_string(), false),
+                Err(_) => (String::from_utf8_lossy(bytes).into_owned(), true),
+            },
+            // The bytes are the code points, encoding_rs taking Latin-1 for Windows-1252 as browsers do
+            Encoding::Latin1 => (bytes.iter().map(|&b| b as char).collect(), false),
+            // Bytes Windows-1252 leaves undefined are read as the control characters of Latin-1,
+            // for any file to be saved as it was
+            Encoding::Windows1252 => (encoding_rs::WINDOWS_1252.decode_without_bom_handling(bytes).0.into_owned(), false),
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let codec = if self == Encoding::Utf16Le { encoding_rs::UTF_16LE } else { encoding_rs::UTF_16BE };
+                let (text, replaced) = codec.decode_without_bom_handling(bytes);
+                (text.into_owned(), replaced)
+            }
+        }
+    }
+
+    // Bytes of the text, or the first character the encoding has none for
+    pub fn encode(self, text: &str) -> Result<Vec<u8>, char> {
+        match self {
+            Encoding::Utf8 => Ok(text.as_bytes().to_vec()),
+            Encoding::Latin1 => text.chars().map(|c| u8::try_from(c).map_err(|_| c)).collect(),
+            Encoding::Windows1252 => {
+                // One byte a character, the most a text of one-byte characters takes
+                let mut bytes = Vec::with_capacity(text.len());
+                let mut encoder = encoding_rs::WINDOWS_1252.new_encoder();
+                match encoder.encode_from_utf8_to_vec_without_replacement(text, &mut bytes, true).0 {
+                    encoding_rs::EncoderResult::Unmappable(c) => Err(c),
+                    _ => Ok(bytes),
+                }
+            }
+            // encoding_rs writes UTF-8 for UTF-16, as browsers do
+            Encoding::Utf16Le | Encoding::Utf16Be => Ok(text.encode_utf16().flat_map(|unit| {
+                if self == Encoding::Utf16Le { unit.to_le_bytes() } else { unit.to_be_bytes() }
+            }).collect()),
+        }
+    }
+}
+
+// Reads a file in the encoding given, or the one found from its bytes, returns its text, its
+// encoding and whether invalid bytes were replaced
+fn read(filename: &str, encoding: Option<Encoding>) -> io::Result<(Rope, Encoding, bool)> {
+    let bytes = fs::read(filename)?;
+    let encoding = encoding.unwrap_or_else(|| Encoding::detect(&bytes));
+    let (text, replaced) = encoding.decode(&bytes);
+    Ok((Rope::from_str(&text), encoding, replaced))
+}
+
+// Format of a preset of the date command, date, datetime and time being those of ISO 8601,
+// or the format itself
+pub fn date_format(format: &str) -> &str {
+    match format {
+        "date" => "%Y-%m-%d",
+        "datetime" => "%Y-%m-%dT%H:%M:%S%:z",
+        "time" => "%H:%M:%S",
+        format => format,
+    }
+}
+
+// A time in a strftime format, which fails on the specifiers chrono does not know
+fn formatted<Tz: TimeZone>(time: &DateTime<Tz>, format: &str) -> Result<String, String>
+where
+    Tz::Offset: fmt::Display,
+{
+    let mut text = String::new();
+    match write!(text, "{}", time.format(format)) {
+        Ok(()) => Ok(text),
+        Err(_) => Err(format!("Invalid date format: {}", format)),
+    }
+}
+
+// Save running on its own thread, with the bytes written so far
+struct Saving {
+    filename: String,
+    written: Arc<AtomicUsize>,
+    result: Receiver<io::Result<()>>,
+}
+
+// Changes the rope as `Editor::terminate` does, calling `touch` with the lines replaced the
+// way `Editor::touch` takes them
+fn terminate(rope: &mut Rope, options: &Options, mut touch: impl FnMut(usize, usize, usize)) {
+    let ending = options.line_ending;
+    if options.trim_trailing || ending != LineEnding::Keep {
+        for line in 0..rope.len_lines() {
+            let slice = rope.line(line);
+            let (max, len) = (columns(slice), slice.len_chars());
+            let text = slice.slice(..max).chars().collect::<String>();
+            let kept = if options.trim_trailing { text.trim_end_matches([' ', '\t']).chars().count() } else { max };
+            let broken = if ending != LineEnding::Keep && len > max { ending.text() } else { "" };
+            if kept == max && (broken.is_empty() || slice.slice(max..) == broken) {
+                continue;
+            }
+            let start = rope.line_to_char(line);
+            rope.remove(start + kept..start + if broken.is_empty() { max } else { len });
+            rope.insert(start + kept, broken);
+            touch(line, 1, 1);
+        }
+    }
+    let len = rope.len_chars();
+    if options.final_newline && len > 0 && !is_break(rope.char(len - 1)) {
+        rope.insert(len, ending.text());
+        touch(lines(rope) - 1, 1, 2);
+    }
+}
+
+// Writes the text to a file next to the one it saves, renamed over it once complete so that
+// the file is never left half written, counting the bytes in `written` as they go. The
+// permissions of the file are kept. Where no file can be created next to it, it is written in place.
+fn store(rope: &Rope, filename: &str, encoding: Encoding, written: &AtomicUsize) -> io::Result<()> {
+    let write = |file: &mut File| -> io::Result<()> {
+        file.write_all(encoding.bom())?;
+        for chunk in rope.chunks() {
+            if encoding == Encoding::Utf8 {
+                file.write_all(chunk.as_bytes())?;
+            } else {
+                let bytes = encoding.encode(chunk).map_err(|c| {
+                    io::Error::new(io::ErrorKind::InvalidData, format!("{:?} cannot be written in {}", c, encoding.name()))
+                })?;
+                file.write_all(&bytes)?;
+            }
+            written.fetch_add(chunk.len(), Ordering::Relaxed);
+        }
+        file.sync_all()
+    };
+
+    // Links are followed so that the file they point to is the one replaced
+    let path = fs::canonicalize(filename).unwrap_or_else(|_| PathBuf::from(filename));
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let temporary = path.with_file_name(format!(".{}.ted~", name));
+    let mut file = match File::create(&temporary) {
+        Ok(file) => file,
+        Err(_) => return write(&mut File::create(&path)?),
+    };
+    let result = fs::metadata(&path)
+        .and_then(|metadata| file.set_permissions(metadata.permissions()))
+        .or_else(|err| if err.kind() == io::ErrorKind::NotFound { Ok(()) } else { Err(err) })
+        .and_then(|()| write(&mut file))
+        .and_then(|()| fs::rename(&temporary, &path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    result
+}
+
+// What moving left at the start of the buffer or right at its end does
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edge {
+    Stop,
+    // Stop and ring the bell
+    Bell,
+    // Go to the other end of the buffer
+    Wrap,
+}
+
+// Line breaks a buffer is saved with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEnding {
+    // Each line keeps the one it has
+    Keep,
+    Lf,
+    CrLf,
+    Cr,
+}
+
+impl LineEnding {
+    fn text(self) -> &'static str {
+        match self {
+            LineEnding::Keep | LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+}
+
+// How lines differ from the version of their file in the git index
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Mark {
+    Added,
+    Changed,
+    // Lines were deleted after this one
+    Deleted,
+    // Only in this text, compared with another one
+    Removed,
+}
+
+// First line, number of lines and kind of each hunk of the changes between a file and
+// the git index, none when the file is not in a repository or git cannot be run
+pub fn diff(filename: &str) -> Vec<(usize, usize, Mark)> {
+    let path = Path::new(filename);
+    let (directory, name) = match (path.parent(), path.file_name()) {
+        (Some(directory), Some(name)) if !directory.as_os_str().is_empty() => (directory, name),
+        (_, Some(name)) => (Path::new("."), name),
+        _ => return Vec::new(),
+    };
+    let output = process::Command::new("git")
+        .arg("-C").arg(directory)
+        .args(["diff", "--no-color", "--no-ext-diff", "-U0", "--"]).arg(name)
+        .stdin(process::Stdio::null())
+        .stderr(process::Stdio::null())
+        .output();
+    let output = match output {
+        Ok(output) if output.status.success() => output,
+        _ => return Vec::new(),
+    };
+
+    // Hunk headers read `@@ -start,count +start,count @@`, a count of 1 being left out
+    let range = |range: &str| {
+        let mut parts = range[1..].split(',');
+        let start = parts.next()?.parse::<usize>().ok()?;
+        let count = parts.next().map_or(Some(1), |count| count.parse::<usize>().ok())?;
+        Some((start, count))
+    };
+    String::from_utf8_lossy(&output.stdout).lines().filter(|line| line.starts_with("@@ ")).filter_map(|line| {
+        let mut parts = line.split_whitespace().skip(1);
+        let (_, old) = range(parts.next()?)?;
+        let (start, new) = range(parts.next()?)?;
+        Some(match (old, new) {
+            (_, 0) => (start.saturating_sub(1), 1, Mark::Deleted),
+            (0, _) => (start - 1, new, Mark::Added),
+            _ => (start - 1, new, Mark::Changed),
+        })
+    }).collect()
+}
+
+// A row of two texts compared side by side, with the line of each it shows
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Row {
+    Same(usize, usize),
+    Changed(usize, usize),
+    // Only in the first text, facing a filler row in the second
+    Removed(usize),
+    // Only in the second text
+    Added(usize),
+}
+
+// Compares two texts line by line, returns the rows showing them side by side. Lines
+// removed and added between the same lines face each other as changed, as far as they go.
+pub fn compare(a: &Rope, b: &Rope) -> Vec<Row> {
+    // Lines are compared by a number for each different line, the same in both texts
+    let mut numbers = HashMap::new();
+    let mut text = |rope: &Rope| (0..rope.len_lines()).map(|i| {
+        let line = rope.line(i);
+        let count = numbers.len();
+        *numbers.entry(line.slice(..columns(line)).to_string()).or_insert(count)
+    }).collect::<Vec<usize>>();
+    let (a, b) = (text(a), text(b));
+
+    // Lines only in one of the texts cannot be paired and are left out of the search,
+    // making it quick for texts with little in common
+    let (in_a, in_b) = (a.iter().collect::<HashSet<_>>(), b.iter().collect::<HashSet<_>>());
+    let kept_a = (0..a.len()).filter(|&i| in_b.contains(&a[i])).collect::<Vec<usize>>();
+    let kept_b = (0..b.len()).filter(|&j| in_a.contains(&b[j])).collect::<Vec<usize>>();
+    let only = |text: &[usize], kept: &[usize]| kept.iter().map(|&i| text[i]).collect::<Vec<usize>>();
+    let mut pairs = common(&only(&a, &kept_a), &only(&b, &kept_b)).into_iter().map(|(i, j)| (kept_a[i], kept_b[j])).collect::<Vec<_>>();
+    pairs.push((a.len(), b.len()));
+
+    let mut rows = Vec::with_capacity(max(a.len(), b.len()));
+    let (mut i, mut j) = (0, 0);
+    for (next_i, next_j) in pairs {
+        let changed = min(next_i - i, next_j - j);
+        rows.extend((0..changed).map(|k| Row::Changed(i + k, j + k)));
+        rows.extend((i + changed..next_i).map(Row::Removed));
+        rows.extend((j + changed..next_j).map(Row::Added));
+        if next_i < a.len() && next_j < b.len() {
+            rows.push(Row::Same(next_i, next_j));
+        }
+        (i, j) = (next_i + 1, next_j + 1);
+    }
+    rows
+}
+
+// Pairs of equal lines of two texts, in order and as many as can be, found with Myers' algorithm
+fn common(a: &[usize], b: &[usize]) -> Vec<(usize, usize)> {
+    let mut pairs = Vec::new();
+    for op in similar::capture_diff_slices(similar::Algorithm::Myers, a, b) {
+        if let similar::DiffOp::Equal { old_index, new_index, len } = op {
+            pairs.extend((0..len).map(|k| (old_index + k, new_index + k)));
+        }
+    }
+    pairs
+}
+
+// Expands the `{a,b}` alternatives of an EditorConfig glob into the globs they stand for
+fn alternatives(glob: &str) -> Vec<String> {
+    let open = match glob.find('{') {
+        Some(open) => open,
+        None => return vec![glob.to_string()],
+    };
+    let (mut depth, mut parts, mut start) = (0, Vec::new(), open + 1);
+    for (i, c) in glob.char_indices().skip_while(|&(i, _)| i <= open) {
+        match c {
+            '{' => depth += 1,
+            '}' if depth > 0 => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&glob[start..i]);
+                start = i + 1;
+            }
+            '}' => {
+                parts.push(&glob[start..i]);
+                // A single word in braces is taken as it is
+                if parts.len() == 1 {
+                    return alternatives(&glob[i + 1..]).into_iter().map(|rest| format!("{}{}", &glob[..=i], rest)).collect();
+                }
+                return parts.iter().flat_map(|part| alternatives(&format!("{}{}{}", &glob[..open], part, &glob[i + 1..]))).collect();
+            }
+            _ => {}
+        }
+    }
+    vec![glob.to_string()]
+}
+
+// Whether a path matches an EditorConfig glob without braces: `*` stands for any characters
+// but '/', `**` for any at all, `?` for one and `[...]` for one of a set, `!` negating it
+fn matches(glob: &[char], path: &[char]) -> bool {
+    match glob.first() {
+        None => path.is_empty(),
+        Some('*') if glob.get(1) == Some(&'*') => (0..=path.len()).any(|i| matches(&glob[2..], &path[i..])),
+        Some('*') => (0..=path.len()).take_while(|&i| i == 0 || path[i - 1] != '/').any(|i| matches(&glob[1..], &path[i..])),
+        Some('?') => path.first().is_some_and(|&c| c != '/') && matches(&glob[1..], &path[1..]),
+        Some('[') => {
+            // Without a closing bracket, or with nothing between the brackets, it is no set
+            let start = if glob.get(1) == Some(&'!') { 2 } else { 1 };
+            let end = match glob[start..].iter().position(|&c| c == ']') {
+                Some(0) | None => return path.first() == Some(&'[') && matches(&glob[1..], &path[1..]),
+                Some(at) => start + at,
+            };
+            let (negated, set) = (start == 2, &glob[start..end]);
+            let c = match path.first() { Some(&c) => c, None => return false };
+            let mut found = false;
+            let mut i = 0;
+            while i < set.len() {
+                if i + 2 < set.len() && set[i + 1] == '-' {
+                    found |= set[i] <= c && c <= set[i + 2];
+                    i += 3;
+                } else {
+                    found |= set[i] == c;
+                    i += 1;
+                }
+            }
+            found != negated && matches(&glob[end + 1..], &path[1..])
+        }
+        Some('\\') if glob.len() > 1 => path.first() == Some(&glob[1]) && matches(&glob[2..], &path[1..]),
+        Some(&c) => path.first() == Some(&c) && matches(&glob[1..], &path[1..]),
+    }
+}
+
+// Properties the `.editorconfig` files in the directories of a file and above give it, those of
+// a file nearer to it and of a later section taking precedence. Files further up than one with
+// `root = true` are not read.
+pub fn editorconfig(filename: &str) -> Vec<(String, String)> {
+    let path = match fs::canonicalize(filename).or_else(|_| std::env::current_dir().map(|dir| dir.join(filename))) {
+        Ok(path) => path,
+        Err(_) => return Vec::new(),
+    };
+    let mut files = Vec::new();
+    for directory in path.ancestors().skip(1) {
+        let text = match fs::read_to_string(directory.join(".editorconfig")) {
+            Ok(text) => text,
+            Err(_) => continue,
+        };
+        let root = text.lines().map(str::trim).take_while(|line| !line.starts_with('[')).any(|line| {
+            line.split_once('=').is_some_and(|(key, value)| key.trim().eq_ignore_ascii_case("root") && value.trim().eq_ignore_ascii_case("true"))
+        });
+        files.push((directory, text));
+        if root {
+            break;
+        }
+    }
+
+    let mut properties: Vec<(String, String)> = Vec::new();
+    for (directory, text) in files.iter().rev() {
+        let relative = path.strip_prefix(directory).unwrap_or(&path).to_string_lossy().chars().collect::<Vec<char>>();
+        let mut matching = false;
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some(section) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                // Globs without a '/' match the file name in any directory
+                let glob = match section.strip_prefix('/') {
+                    Some(glob) => glob.to_string(),
+                    None if section.contains('/') => section.to_string(),
+                    None => format!("**/{}", section),
+                };
+                matching = alternatives(&glob).iter().any(|glob| {
+                    let glob = glob.chars().collect::<Vec<char>>();
+                    matches(&glob, &relative) || glob.starts_with(&['*', '*', '/']) && matches(&glob[3..], &relative)
+                });
+            } else if let (true, Some((key, value))) = (matching, line.split_once('=')) {
+                let (key, value) = (key.trim().to_lowercase(), value.trim().to_lowercase());
+                properties.retain(|(other, _)| *other != key);
+                properties.push((key, value));
+            }
+        }
+    }
+    properties
+}
+
+pub struct Options {
+    pub auto_indent: bool,
+    // Backspace right after a line break takes away the whole indentation it was given
+    pub backspace_indent: bool,
+    // Indent a level more after an opening bracket and a level less before a closing one
+    pub smart_indent: bool,
+    // Insert the closing bracket or quote along with the opening one, and type over it
+    pub auto_pairs: bool,
+    // Insert spaces up to the next multiple of the indent width instead of a tab character
+    pub soft_tabs: bool,
+    // Cells between the tab stops tab characters are drawn up to
+    pub tab_width: usize,
+    // Columns of a level of indentation in spaces, the tab width when 0
+    pub indent_width: usize,
+    // Report keys and sequences that have no binding on the status row
+    pub show_unmapped: bool,
+    pub indent_guides: bool,
+    // Mark the column a cursor past the end of its line goes back to on longer lines
+    pub desired_column: bool,
+    // Marks of the changed lines left of the text, with spaces before and after them and
+    // a character between them and the text, if any
+    pub gutter_marks: bool,
+    pub gutter_padding: (usize, usize),
+    pub gutter_separator: String,
+    // Draw the cell past the end of a line as selected when a selection goes on over its line break
+    pub select_newline: bool,
+    // Overview of the whole buffer down the right edge of the view
+    pub minimap: bool,
+    // Mark the other occurrences of the word the cursor rests on
+    pub highlight_word: bool,
+    pub edge: Edge,
+    // End the file with a line break when saving, if it does not already
+    pub final_newline: bool,
+    // Remove the spaces and tabs at the end of lines when saving
+    pub trim_trailing: bool,
+    pub line_ending: LineEnding,
+    // Width and way lines are wrapped by reflow: at the last space that fits, or when
+    // not wrapping words, at the last character that fits
+    pub text_width: usize,
+    pub word_wrap: bool,
+    // Lines moved by PageUp and PageDown, the height of the view when 0
+    pub page_size: usize,
+    // Lines kept visible above and below the cursor
+    pub scroll_off: usize,
+    // Cursors past which a key editing at all of them is to be confirmed, never when 0
+    pub cursor_limit: usize,
+    // Let the view scroll down until the last line is at its top, instead of its bottom
+    pub scroll_past_end: bool,
+    // Leave pinned cursors where they are when the others move
+    pub hold_pinned: bool,
+    // Cells past which the characters of a line are marked, none when 0
+    pub max_line_length: usize,
+    // Set soft tabs and the tab width from the indentation of the file when it is opened
+    pub detect_indent: bool,
+    // Mark the words missing from the dictionary, a file of one word per line
+    pub spell: bool,
+    pub dictionary: String,
+    // Characters besides letters and digits that words are made of
+    pub word_chars: String,
+    // Format of the date inserted by the date command, in strftime or a preset
+    pub date_format: String,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            auto_indent: true,
+            backspace_indent: true,
+            smart_indent: false,
+            auto_pairs: false,
+            soft_tabs: false,
+            tab_width: 4,
+            indent_width: 0,
+            show_unmapped: false,
+            indent_guides: false,
+            desired_column: false,
+            gutter_marks: true,
+            gutter_padding: (0, 1),
+            gutter_separator: String::new(),
+            select_newline: true,
+            minimap: false,
+            highlight_word: false,
+            edge: Edge::Stop,
+            final_newline: false,
+            trim_trailing: false,
+            line_ending: LineEnding::Keep,
+            text_width: 79,
+            word_wrap: true,
+            page_size: 0,
+            scroll_off: 0,
+            cursor_limit: 50,
+            scroll_past_end: false,
+            hold_pinned: true,
+            max_line_length: 0,
+            detect_indent: true,
+            spell: false,
+            dictionary: "/usr/share/dict/words".to_string(),
+            word_chars: "_".to_string(),
+            date_format: "datetime".to_string(),
+        }
+    }
+}
+
+impl Options {
+    pub fn is_word(&self, c: char) -> bool {
+        c.is_alphanumeric() || self.word_chars.contains(c)
+    }
+
+    pub fn indent_width(&self) -> usize {
+        if self.indent_width > 0 { self.indent_width } else { self.tab_width.max(1) }
+    }
+
+    // Cells of the gutter, when it is shown
+    pub fn gutter_width(&self) -> usize {
+        let (before, after) = self.gutter_padding;
+        before + 1 + after + self.gutter_separator.chars().map(width).sum::<usize>()
+    }
+}
+
+// Last mutation, replayed by the repeat key. Consecutive edits of the same kind
+// form a single change until a key that does not edit the buffer ends the run.
+#[derive(Clone)]
+enum Change {
+    Insert(String),
+    Backspace(usize),
+    Delete(usize),
+    DeleteLine(usize),
+    Paste(usize),
+    Split(usize),
+    Open { above: bool, count: usize },
+    Duplicate(usize),
+    Case(usize),
+    Comment,
+    Swap,
+}
+
+pub struct Editor {
+    pub rope: Rope,
+    pub cursors: Vec<Cursor>,
+    pub filename: Option<String>,
+    // Encoding the file was read in and is saved in
+    pub encoding: Encoding,
+    // Extension the buffer is taken to have when its file name has none, as for text read from a pipe
+    pub language: Option<String>,
+    pub dirty: bool,
+    pub readonly: bool,
+    // Viewport offsets (x, y) saved while the buffer is not displayed
+    pub scroll: (usize, usize),
+    pub options: Options,
+    // Disables auto-indent while text is pasted without bracketed paste support
+    pub paste_mode: bool,
+    // Typed characters replace the ones under the cursors instead of being inserted before them
+    pub overwrite: bool,
+    pub message: Option<(String, Instant)>,
+    // Line jumped to, highlighted for a moment so it is easy to find
+    pub flash: Option<(usize, Instant)>,
+    // Keys typed since recording started, and the last recorded macro
+    pub recording: Option<Vec<Key>>,
+    pub recorded: Vec<Key>,
+    pub count: Option<usize>,
+    // Lines the view should scroll along with the cursor, taken by the front end after each key
+    pub scrolled: isize,
+    // Whether to ring the bell, taken by the front end after each key
+    pub bell: bool,
+    // External commands run after a successful save, by file extension
+    pub hooks: Vec<(String, String)>,
+    // Lines deleted last, each with its line break, in one piece per line of a cursor
+    pub register: Vec<String>,
+    // Word searched for last
+    pub search: Option<String>,
+    // Word under the cursor whose occurrences are marked, and where the cursor came to rest
+    // and since when, until the word is looked for
+    pub highlighted: Option<String>,
+    rest: ((usize, usize), Option<Instant>),
+    // Whether each line was changed since the buffer was read or saved, empty if none was
+    pub modified: Vec<bool>,
+    // Words of the dictionary in lower case, read when spell checking is turned on
+    words: HashSet<String>,
+    // Hunks of the changes between the file and the git index, when it was last read or saved
+    pub hunks: Vec<(usize, usize, Mark)>,
+    diffing: Option<Receiver<Vec<(usize, usize, Mark)>>>,
+    saving: Option<Saving>,
+    // First and last lines hidden under the line before them, dropped when lines are added or removed
+    pub folds: Vec<(usize, usize)>,
+    // Row of each line shown side by side with another buffer it was compared with, the rows
+    // left out being fillers facing lines only in the other one, and the number of rows
+    pub aligned: Option<(Vec<usize>, usize)>,
+    // Bumped on each change to the text or the options, which the buffer is drawn from
+    pub revision: usize,
+    // Whether the last key broke lines and indented the new ones
+    indented: bool,
+    change: Option<Change>,
+    changes: usize,
+    run: bool,
+}
+
+impl Editor {
+    pub fn new(rope: Rope) -> Self {
+        let mut editor = Self {
+            rope,
+            cursors: Vec::with_capacity(4),
+            filename: None,
+            encoding: Encoding::Utf8,
+            language: None,
+            dirty: false,
+            readonly: false,
+            scroll: (0, 0),
+            options: Options::default(),
+            paste_mode: false,
+            overwrite: false,
+            message: None,
+            flash: None,
+            recording: None,
+            recorded: Vec::new(),
+            count: None,
+            scrolled: 0,
+            bell: false,
+            hooks: Vec::new(),
+            register: Vec::new(),
+            search: None,
+            highlighted: None,
+            rest: ((0, 0), None),
+            modified: Vec::new(),
+            words: HashSet::new(),
+            hunks: Vec::new(),
+            diffing: None,
+            saving: None,
+            folds: Vec::new(),
+            aligned: None,
+            revision: 0,
+            indented: false,
+            change: None,
+            changes: 0,
+            run: false,
+        };
+
+        editor.cursors.push(Cursor::new(0 ,0));
+        editor
+    }
+
+    pub fn open(filename: String) -> Self {
+        Self::open_as(filename, None)
+    }
+
+    // Opens a file in an encoding, or the one found from its bytes when not given
+    pub fn open_as(filename: String, encoding: Option<Encoding>) -> Self {
+        if Path::new(&filename).is_dir() {
+            return Self::listing(filename);
+        }
+        let (rope, encoding, replaced) = read(&filename, encoding)
+            .unwrap_or_else(|_| (Rope::new(), encoding.unwrap_or(Encoding::Utf8), false));
+
+        let mut editor = Self::new(rope);
+        editor.encoding = encoding;
+        editor.decoded(&filename, replaced);
+        editor.filename = Some(filename);
+        editor.rediff();
+        editor
+    }
+
+    // Warns about text that may not be saved as it was read
+    fn decoded(&mut self, name: &str, replaced: bool) {
+        if replaced {
+            self.set_message(format!("\"{}\" has bytes invalid in {}, replaced", name, self.encoding.name()));
+        } else if self.encoding == Encoding::Windows1252 {
+            self.set_message(format!("\"{}\" is not UTF-8, read as {}", name, self.encoding.name()));
+        }
+    }
+
+    pub fn extension(&self) -> Option<&str> {
+        self.filename.as_ref()
+            .and_then(|filename| Path::new(filename).extension())
+            .and_then(|extension| extension.to_str())
+            .or(self.language.as_deref())
+    }
+
+    // Compares the file with the git index on a thread of its own, the result being
+    // picked up by `expire`
+    fn rediff(&mut self) {
+        if let Some(filename) = self.filename.clone().filter(|filename| Path::new(filename).is_file()) {
+            let (sender, receiver) = channel();
+            thread::spawn(move || sender.send(diff(&filename)));
+            self.diffing = Some(receiver);
+        }
+    }
+
+    // Kind of the hunk a line is part of
+    pub fn hunk(&self, line: usize) -> Option<Mark> {
+        self.hunks.iter().find(|&&(first, count, _)| first <= line && line < first + count.max(1)).map(|&(_, _, mark)| mark)
+    }
+
+    // Shows the buffer on its side of the rows it was compared with another on, read-only,
+    // with the lines that differ marked in place of the changes from git
+    pub fn compared(&mut self, rows: &[Row], first: bool) {
+        let mut aligned = Vec::with_capacity(self.rope.len_lines());
+        let mut hunks: Vec<(usize, usize, Mark)> = Vec::new();
+        for (i, &row) in rows.iter().enumerate() {
+            let (line, mark) = match (row, first) {
+                (Row::Same(line, _), true) | (Row::Same(_, line), false) => (line, None),
+                (Row::Changed(line, _), true) | (Row::Changed(_, line), false) => (line, Some(Mark::Changed)),
+                (Row::Removed(line), true) => (line, Some(Mark::Removed)),
+                (Row::Added(line), false) => (line, Some(Mark::Added)),
+                _ => continue,
+            };
+            aligned.push(i);
+            if let Some(mark) = mark {
+                match hunks.last_mut() {
+                    Some((start, count, last)) if *last == mark && *start + *count == line => *count += 1,
+                    _ => hunks.push((line, 1, mark)),
+                }
+            }
+        }
+        self.aligned = Some((aligned, rows.len()));
+        self.hunks = hunks;
+        self.diffing = None;
+        self.readonly = true;
+        self.folds.clear();
+    }
+
+    // Lists the entries of a directory, subdirectories first, to be opened from the buffer
+    fn listing(directory: String) -> Self {
+        let mut directories = Vec::new();
+        let mut files = Vec::new();
+        if let Ok(entries) = fs::read_dir(&directory) {
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if entry.path().is_dir() { directories.push(format!("{}/", name)) } else { files.push(name) }
+            }
+        }
+        directories.sort();
+        files.sort();
+
+        let mut entries = vec!["../".to_string()];
+        entries.append(&mut directories);
+        entries.append(&mut files);
+        let mut editor = Self::new(Rope::from_str(&entries.join("\n")));
+        editor.filename = Some(directory);
+        editor.readonly = true;
+        editor
+    }
+
+    // Path of the entry under the primary cursor in a directory listing
+    pub fn entry(&self) -> Option<PathBuf> {
+        let directory = Path::new(self.filename.as_ref()?);
+        if !self.readonly || !directory.is_dir() {
+            return None;
+        }
+        let line = self.rope.line(self.line());
+        Some(directory.join(line.slice(..columns(line)).to_string()))
+    }
+
+    // Sets soft tabs and the tab width to match the indentation of the first indented lines,
+    // keeping the options as they are when the buffer has none or mixes tabs and spaces
+    pub fn detect_indentation(&mut self) {
+        if !self.options.detect_indent {
+            return;
+        }
+        let (mut tabs, mut spaces) = (0, 0);
+        // Number of lines indented by each number of spaces more than the line before them
+        let mut steps = [0; 9];
+        let mut previous = 0;
+        for line in self.rope.lines().filter(|line| line.chars().take(columns(*line)).any(|c| !c.is_whitespace())).take(1000) {
+            let indent = indentation(line);
+            if indent.starts_with('\t') {
+                tabs += 1;
+            } else if !indent.is_empty() {
+                spaces += 1;
+            }
+            if !indent.contains('\t') {
+                let depth = indent.chars().count();
+                if depth > previous && depth - previous < steps.len() {
+                    steps[depth - previous] += 1;
+                }
+                previous = depth;
+            }
+        }
+
+        if tabs > spaces * 2 {
+            self.options.soft_tabs = false;
+            self.options.indent_width = 0;
+            self.set_message("Indentation: tabs");
+        } else if spaces > tabs * 2 {
+            // The most common step, the smallest one in a tie
+            let (width, &count) = steps.iter().enumerate().skip(1).rev().max_by_key(|&(_, count)| count).unwrap();
+            if count > 0 {
+                self.options.soft_tabs = true;
+                self.options.indent_width = width;
+                self.set_message(format!("Indentation: {} spaces", width));
+            }
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        if let Some(filename) = &self.filename { filename } else { "[No Name]" }
+    }
+
+    // Stands for all the buffer is drawn from, the same as long as it would be drawn the same:
+    // the text and options by their revision, the cursors and what the status row tells
+    pub fn appearance(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.revision.hash(&mut hasher);
+        for cursor in &self.cursors {
+            (cursor.line, cursor.col, cursor.anchor, cursor.pinned).hash(&mut hasher);
+        }
+        self.message.as_ref().map(|(message, _)| message).hash(&mut hasher);
+        (self.flash.map(|(line, _)| line), &self.highlighted, &self.hunks, &self.folds).hash(&mut hasher);
+        (&self.filename, self.dirty, self.readonly, self.paste_mode, self.overwrite).hash(&mut hasher);
+        (self.recording.is_some(), self.count, self.encoding.name(), self.saving()).hash(&mut hasher);
+        hasher.finish()
+    }
+
+    // Characters around the primary cursor matching `predicate`
+    fn token<P: Fn(char) -> bool>(&self, predicate: P) -> String {
+        let line = self.rope.line(self.line());
+        let col = self.col();
+        let mut start = col;
+        while start > 0 && predicate(line.char(start - 1)) {
+            start -= 1;
+        }
+        let end = col + line.slice(col..).chars().take_while(|&c| predicate(c)).count();
+        line.slice(start..end).to_string()
+    }
+
+    // Path under the primary cursor, relative to the directory of the buffer's file
+    pub fn path_under_cursor(&self) -> Option<PathBuf> {
+        let token = self.token(is_path_char);
+        if token.is_empty() {
+            return None;
+        }
+        let directory = self.filename.as_ref().and_then(|filename| Path::new(filename).parent());
+        Some(directory.map(|directory| directory.join(&token)).unwrap_or_else(|| PathBuf::from(token)))
+    }
+
+    pub fn set_message<S: Into<String>>(&mut self, msg: S) {
+        self.message = Some((msg.into(), Instant::now()));
+    }
+
+    // Time left before the next timed state change, if any
+    pub fn timeout(&self) -> Option<Duration> {
+        let message = self.message.as_ref().map(|(_, at)| MESSAGE_TIMEOUT.checked_sub(at.elapsed()).unwrap_or_default());
+        let flash = self.flash.map(|(_, at)| FLASH_TIMEOUT.checked_sub(at.elapsed()).unwrap_or_default());
+        let diff = self.diffing.as_ref().map(|_| DIFF_POLL);
+        let save = self.saving.as_ref().map(|_| SAVE_POLL);
+        // A cursor moved other than by a key, as by the mouse, comes to rest right away
+        let highlight = match self.rest {
+            _ if !self.options.highlight_word => None,
+            (at, _) if at != (self.line(), self.col()) => Some(Duration::default()),
+            (_, since) => since.map(|since| HIGHLIGHT_DELAY.checked_sub(since.elapsed()).unwrap_or_default()),
+        };
+        [message, flash, diff, save, highlight].iter().flatten().min().copied()
+    }
+
+    // Drops timed state that has expired, returns whether something changed
+    pub fn expire(&mut self) -> bool {
+        let mut expired = false;
+        if let Some((_, at)) = &self.message {
+            if at.elapsed() >= MESSAGE_TIMEOUT {
+                self.message = None;
+                expired = true;
+            }
+        }
+        if let Some((_, at)) = self.flash {
+            if at.elapsed() >= FLASH_TIMEOUT {
+                self.flash = None;
+                expired = true;
+            }
+        }
+        if self.options.highlight_word {
+            let at = (self.line(), self.col());
+            if self.rest.0 != at {
+                self.rest = (at, Some(Instant::now()));
+                expired |= self.highlighted.take().is_some();
+            } else if matches!(self.rest.1, Some(since) if since.elapsed() >= HIGHLIGHT_DELAY) {
+                self.rest.1 = None;
+                let word = self.token(|c| self.options.is_word(c));
+                self.highlighted = if word.is_empty() { None } else { Some(word) };
+                expired |= self.highlighted.is_some();
+            }
+        }
+        if let Some(receiver) = &self.diffing {
+            match receiver.try_recv() {
+                Ok(hunks) => {
+                    self.hunks = hunks;
+                    self.diffing = None;
+                    expired = true;
+                }
+                Err(TryRecvError::Disconnected) => self.diffing = None,
+                Err(TryRecvError::Empty) => {}
+            }
+        }
+        // The progress of a save is shown again on each poll
+        if let Some(saving) = &self.saving {
+            let result = match saving.result.try_recv() {
+                Ok(result) => Some(result),
+                Err(TryRecvError::Disconnected) => Some(Err(io::Error::other("the save was interrupted"))),
+                Err(TryRecvError::Empty) => None,
+            };
+            if let Some(result) = result {
+                let filename = self.saving.take().unwrap().filename;
+                self.saved(&filename, result);
+            }
+            expired = true;
+        }
+        expired
+    }
+
+    // Share of the buffer written by a save on its own thread, in percent
+    pub fn saving(&self) -> Option<usize> {
+        self.saving.as_ref().map(|saving| {
+            saving.written.load(Ordering::Relaxed) * 100 / self.rope.len_bytes().max(1)
+        })
+    }
+
+    // Waits for a save on its own thread to end, returns whether the buffer is saved
+    pub fn finish(&mut self) -> bool {
+        if let Some(saving) = self.saving.take() {
+            let result = saving.result.recv().unwrap_or_else(|_| Err(io::Error::other("the save was interrupted")));
+            self.saved(&saving.filename, result);
+        }
+        !self.dirty
+    }
+
+    pub fn line(&self) -> usize {
+        if let Some(cursor) = self.cursors.first() { cursor.line() } else { 0 }
+    }
+
+    pub fn col(&self) -> usize {
+        if let Some(cursor) = self.cursors.first() { cursor.col(&self.rope) } else { 0 }
+    }
+
+    // Ranges of columns selected on a line
+    pub fn selections_at(&self, line: usize) -> Vec<(usize, usize)> {
+        let start = self.rope.line_to_char(line);
+        let end = start + columns(self.rope.line(line));
+        self.cursors.iter().filter_map(|cursor| cursor.selection(&self.rope))
+            .map(|(from, to)| (from.max(start), min(to, end)))
+            .filter(|(from, to)| from < to)
+            .map(|(from, to)| (from - start, to - start)).collect()
+    }
+
+    // Whether a selection goes on past the end of a line, over its line break
+    pub fn selects_newline(&self, line: usize) -> bool {
+        let end = self.rope.line_to_char(line) + columns(self.rope.line(line));
+        self.cursors.iter().filter_map(|cursor| cursor.selection(&self.rope)).any(|(from, to)| from <= end && end < to)
+    }
+
+    // Columns of the pinned cursors on a line, which are drawn apart from the others
+    pub fn pinned_at(&self, line: usize) -> Vec<usize> {
+        self.cursors.iter().filter(|c| c.line == line && c.pinned).map(|c| c.col(&self.rope)).collect()
+    }
+
+    // Columns past the end of a line the cursors on it go back to on longer lines, in order
+    pub fn desired_at(&self, line: usize) -> Vec<usize> {
+        let mut cols = self.cursors
+            .iter().filter(|c| c.line == line && c.col > c.col(&self.rope))
+            .map(|c| c.col).collect::<Vec<usize>>();
+        cols.sort();
+        cols
+    }
+
+    // Columns of the cursors on a line, in order
+    pub fn cursors_at(&self, line: usize) -> Vec<usize> {
+        let mut cols = self.cursors
+            .iter().filter(|c| c.line == line)
+            .map(|c| c.col(&self.rope)).collect::<Vec<usize>>();
+        cols.sort();
+        cols
+    }
+
+    // Adds a key to the macro being recorded, leaving out those starting and stopping the
+    // recording and playing it back, with the count typed before. The front end records the
+    // keys as it reads them, before they are handled, for keys it handles itself and those
+    // typed in prompts to be recorded too.
+    pub fn record(&mut self, key: Key) {
+        if let Some(keys) = &mut self.recording {
+            if key == Key::Alt('@') {
+                while let Some(Key::Alt(c)) = keys.last() {
+                    if !c.is_ascii_digit() { break }
+                    keys.pop();
+                }
+            } else if key != Key::Alt('q') {
+                keys.push(key);
+            }
+        }
+    }
+
+    pub fn key(&mut self, key: Key, height: usize) -> bool {
+        // Any key, moving the cursor or editing the word under it, starts the wait over
+        self.highlighted = None;
+        self.rest.1 = Some(Instant::now());
+
+        // Alt + digits prefix the next key with a repeat count
+        if let Key::Alt(c) = key {
+            if let Some(digit) = c.to_digit(10) {
+                self.count = Some(self.count.unwrap_or(0) * 10 + digit as usize);
+                return true;
+            }
+        }
+
+        let count = self.count.take().unwrap_or(1);
+        if key != Key::Backspace {
+            self.indented = false;
+        }
+        let changes = self.changes;
+        let len = self.rope.len_lines();
+        let before = self.cursors.iter().map(|cursor| cursor.line).collect::<Vec<usize>>();
+        let draw = (0..count).fold(false, |draw, _| self.dispatch(key, height) || draw);
+        if self.changes == changes {
+            self.run = false;
+        }
+        if self.rope.len_lines() != len {
+            self.folds.clear();
+        }
+        self.skip(&before);
+        draw
+    }
+
+    // Whether a line is hidden by a fold
+    pub fn hidden(&self, line: usize) -> bool {
+        self.folds.iter().any(|&(first, last)| first <= line && line <= last)
+    }
+
+    // Number of lines hidden under a line, if a fold starts right after it
+    pub fn folded(&self, line: usize) -> Option<usize> {
+        self.folds.iter().filter(|&&(first, _)| first == line + 1).map(|&(first, last)| last - first + 1).max()
+    }
+
+    // The line itself, or the line a fold hiding it is under
+    pub fn shown(&self, mut line: usize) -> usize {
+        while line > 0 && self.hidden(line) {
+            line -= 1;
+        }
+        line
+    }
+
+    // Line shown `rows` rows below or above another, stopping at the first or last one shown
+    pub fn below(&self, mut line: usize, rows: usize) -> usize {
+        line = self.shown(line);
+        for _ in 0..rows {
+            match (line + 1..=lines(&self.rope)).find(|&next| !self.hidden(next)) {
+                Some(next) => line = next,
+                None => break,
+            }
+        }
+        line
+    }
+
+    pub fn above(&self, mut line: usize, rows: usize) -> usize {
+        line = self.shown(line);
+        for _ in 0..rows {
+            if line == 0 {
+                break;
+            }
+            line = self.shown(line - 1);
+        }
+        line
+    }
+
+    // Number of lines shown from `from` up to, but not including, `to`
+    pub fn rows(&self, from: usize, to: usize) -> usize {
+        if self.folds.is_empty() {
+            return to.saturating_sub(from);
+        }
+        (from..to).filter(|&line| !self.hidden(line)).count()
+    }
+
+    // Hides the lines after the primary cursor's line that are more indented than it,
+    // or shows them again if they already are
+    fn fold(&mut self) {
+        // Rows compared with another buffer are lined up with its lines, none can be hidden
+        if self.aligned.is_some() {
+            self.set_message("Cannot fold a buffer being compared");
+            return;
+        }
+        let line = self.line();
+        if self.folded(line).is_some() {
+            self.folds.retain(|&(first, _)| first != line + 1);
+            return;
+        }
+
+        let tab_width = self.options.tab_width.max(1);
+        let rope = &self.rope;
+        let blank = |line: usize| rope.line(line).chars().take(columns(rope.line(line))).all(char::is_whitespace);
+        let depth = |line: usize| indentation(rope.line(line)).chars().map(|c| if c == '\t' { tab_width } else { 1 }).sum::<usize>();
+        let mut last = line;
+        let mut next = line + 1;
+        while next <= lines(rope) && (blank(next) || depth(next) > depth(line)) {
+            if !blank(next) {
+                last = next;
+            }
+            next += 1;
+        }
+        if last == line {
+            self.set_message("Nothing to fold");
+        } else {
+            self.folds.push((line + 1, last));
+            self.folds.sort_unstable();
+        }
+    }
+
+    // Moves the cursors out of folds, past them when they were moving down onto them
+    fn skip(&mut self, before: &[usize]) {
+        for i in 0..self.cursors.len() {
+            let line = self.cursors[i].line;
+            if self.hidden(line) {
+                let shown = self.shown(line);
+                let below = self.below(line, 1);
+                let down = before.get(i).is_none_or(|&from| from <= shown);
+                self.cursors[i].apply(&self.rope, Movement::GotoLine(if down && below > line { below } else { shown }));
+            }
+        }
+        self.merge_moved();
+    }
+
+    fn changed(&mut self, change: Change) {
+        self.dirty = true;
+        self.changes += 1;
+        match (&mut self.change, change) {
+            (Some(Change::Insert(text)), Change::Insert(more)) if self.run => text.push_str(&more),
+            (Some(Change::Backspace(n)), Change::Backspace(m)) if self.run => *n += m,
+            (Some(Change::Delete(n)), Change::Delete(m)) if self.run => *n += m,
+            (Some(Change::DeleteLine(n)), Change::DeleteLine(m)) if self.run => *n += m,
+            (Some(Change::Paste(n)), Change::Paste(m)) if self.run => *n += m,
+            (Some(Change::Split(n)), Change::Split(m)) if self.run => *n += m,
+            (Some(Change::Duplicate(n)), Change::Duplicate(m)) if self.run => *n += m,
+            (Some(Change::Case(n)), Change::Case(m)) if self.run => *n += m,
+            (Some(Change::Open { above, count }), Change::Open { above: other, count: more })
+                if self.run && *above == other => *count += more,
+            (_, change) => self.change = Some(change),
+        }
+        self.run = true;
+    }
+
+    // Runs `edit` at each cursor in turn, returns whether any of them changed the buffer.
+    // The other cursors follow the text around them as it moves, and cursors that end up
+    // at the same position are merged so that they do not edit twice.
+    fn edit<F>(&mut self, mut edit: F) -> bool
+    where
+        F: FnMut(&mut Rope, &mut Cursor) -> bool,
+    {
+        if !self.writable() {
+            return false;
+        }
+        self.merge();
+        let mut edited = false;
+        for i in 0..self.cursors.len() {
+            let positions = self.cursors.iter().map(|c| c.pos(&self.rope)).collect::<Vec<usize>>();
+            let len = self.rope.len_chars();
+            let count = self.rope.len_lines();
+            if !edit(&mut self.rope, &mut self.cursors[i]) {
+                continue;
+            }
+            edited = true;
+
+            // Text was inserted or removed from the leftmost of the cursor positions
+            let at = min(positions[i], self.cursors[i].pos(&self.rope));
+            let new = self.rope.len_lines();
+            self.touch(self.rope.char_to_line(at), 1 + count.saturating_sub(new), 1 + new.saturating_sub(count));
+            let new = self.rope.len_chars();
+            for (j, cursor) in self.cursors.iter_mut().enumerate() {
+                if j == i {
+                    continue;
+                }
+                let pos = positions[j];
+                let pos = if new >= len {
+                    if pos >= at { pos + new - len } else { pos }
+                } else if pos >= at + len - new {
+                    pos - (len - new)
+                } else {
+                    min(pos, at)
+                };
+                let line = self.rope.char_to_line(pos);
+                cursor.apply(&self.rope, Movement::Goto(line, pos - self.rope.line_to_char(line)));
+            }
+        }
+        // Selections do not survive edits
+        if edited {
+            for cursor in &mut self.cursors {
+                cursor.anchor = None;
+            }
+        }
+        self.merge();
+        edited
+    }
+
+    // Records that `removed` lines from `line` were replaced by `added` changed lines
+    fn touch(&mut self, line: usize, removed: usize, added: usize) {
+        self.revision += 1;
+        if self.modified.is_empty() {
+            self.modified = vec![false; self.rope.len_lines() + removed - added];
+        }
+        let end = min(line + removed, self.modified.len());
+        self.modified.splice(min(line, end)..end, vec![true; added]);
+        for hunk in &mut self.hunks {
+            if hunk.0 > line {
+                hunk.0 = (hunk.0 + added).saturating_sub(removed);
+            }
+        }
+    }
+
+    // Moves the primary cursor to the first line of the next or previous run of lines
+    // changed since the file was saved, or hunk of changes from the git index
+    fn modification(&mut self, forward: bool) {
+        if !self.modified.contains(&true) && self.hunks.is_empty() {
+            self.set_message("No changes");
+            return;
+        }
+        let marked = |line: usize| self.modified.get(line) == Some(&true) || self.hunk(line).is_some();
+        let start = |line: &usize| marked(*line) && (*line == 0 || !marked(*line - 1));
+        let (line, last) = (self.line(), lines(&self.rope));
+        let found = if forward {
+            (line + 1..=last).find(start).or_else(|| (0..=line).find(start))
+        } else {
+            (0..line).rev().find(start).or_else(|| (line..=last).rev().find(start))
+        };
+        if let Some(found) = found {
+            self.jump(found, 0);
+        }
+    }
+
+    // Number of cursors a key would start editing the buffer at, when more than the limit of
+    // the options, for the front end to have it confirmed before handling it. Keys going on
+    // with a run of edits are not held up again.
+    pub fn guarded(&self, key: Key) -> Option<usize> {
+        let limit = self.options.cursor_limit;
+        if limit == 0 || self.cursors.len() <= limit || self.run || self.readonly {
+            return None;
+        }
+        if Self::edits(key) { Some(self.cursors.len()) } else { None }
+    }
+
+    // Keys of the arms of `dispatch` that change the text, to be kept in step with them
+    fn edits(key: Key) -> bool {
+        matches!(key, Key::Char(_) | Key::Backspace | Key::Delete | Key::Ctrl('k') | Key::Ctrl('y') | Key::Alt('y')
+            | Key::Ctrl('o') | Key::Alt('o') | Key::Alt('u') | Key::Alt('d') | Key::Alt('~') | Key::Alt('/')
+            | Key::Ctrl('7') | Key::Alt('.') | Key::Alt('@'))
+    }
+
+    // Whether auto-pairs apply to the keys typed, not while pasting or overwriting
+    fn pairing(&self) -> bool {
+        self.options.auto_pairs && !self.paste_mode && !self.overwrite
+    }
+
+    // Tells on the status row when the buffer cannot be changed
+    fn writable(&mut self) -> bool {
+        if self.readonly {
+            self.set_message("Buffer is read-only");
+        } else if self.saving.is_some() {
+            self.set_message("Buffer is read-only until it is saved");
+        }
+        !self.readonly && self.saving.is_none()
+    }
+
+    // Drops the cursors at the same position as an earlier one
+    fn merge(&mut self) {
+        let rope = &self.rope;
+        let mut positions = Vec::with_capacity(self.cursors.len());
+        self.cursors.retain(|cursor| {
+            let pos = cursor.pos(rope);
+            if positions.contains(&pos) { false } else { positions.push(pos); true }
+        });
+    }
+
+    // Drops the cursors at the same position as an earlier one and keeping the same column to
+    // go back to on longer lines, so that cursors meeting at the end of a short line part again.
+    // Edits merge them all first.
+    fn merge_moved(&mut self) {
+        let rope = &self.rope;
+        let mut positions = Vec::with_capacity(self.cursors.len());
+        self.cursors.retain(|cursor| {
+            let pos = (cursor.pos(rope), cursor.col);
+            if positions.contains(&pos) { false } else { positions.push(pos); true }
+        });
+    }
+
+    fn dispatch(&mut self, key: Key, height: usize) -> bool {
+        // Moving with Shift starts or extends the selections, moving without drops them
+        let typed = key;
+        let (key, shifted) = if let Key::Shift(nav) = key { (Key::from(nav), true) } else { (key, false) };
+        let movement = matches!(key, Key::Up | Key::Down | Key::Left | Key::Right | Key::Home | Key::End
+            | Key::PageUp | Key::PageDown | Key::Ctrl('u') | Key::Ctrl('d') | Key::Ctrl('a') | Key::Ctrl('e'));
+
+        // Pinned cursors are put back where they were once the others have moved, unless
+        // there are no others
+        if movement && self.options.hold_pinned && self.cursors.iter().any(|cursor| cursor.pinned)
+            && self.cursors.iter().any(|cursor| !cursor.pinned) {
+            let held = self.cursors.iter_mut().enumerate().filter(|(_, cursor)| cursor.pinned).map(|(i, cursor)| {
+                cursor.pinned = false;
+                (i, cursor.line, cursor.col, cursor.anchor)
+            }).collect::<Vec<_>>();
+            let draw = self.dispatch(typed, height);
+            for (i, line, col, anchor) in held {
+                self.cursors[i] = Cursor { line, col, anchor, pinned: true };
+            }
+            return draw;
+        }
+        if movement {
+            for cursor in &mut self.cursors {
+                cursor.anchor = if shifted { Some(cursor.anchor.unwrap_or((cursor.line, cursor.col(&self.rope)))) } else { None };
+            }
+        }
+
+        match key {
+            Key::Up => {
+                for cursor in &mut self.cursors {
+                    cursor.apply(&self.rope, Movement::Up(1));
+                }
+                true
+            }
+            Key::Down => {
+                for cursor in &mut self.cursors {
+                    cursor.apply(&self.rope, Movement::Down(1));
+                }
+                true
+            }
+            Key::Left => {
+                let mut edge = false;
+                for cursor in &mut self.cursors {
+                    if cursor.pos(&self.rope) == 0 {
+                        edge = true;
+                        if self.options.edge == Edge::Wrap {
+                            cursor.apply(&self.rope, Movement::End);
+                            continue;
+                        }
+                    }
+                    cursor.apply(&self.rope, Movement::Left(1));
+                }
+                self.bell |= edge && self.options.edge == Edge::Bell;
+                true
+            }
+            Key::Right => {
+                let mut edge = false;
+                for cursor in &mut self.cursors {
+                    if cursor.pos(&self.rope) == end(&self.rope) {
+                        edge = true;
+                        if self.options.edge == Edge::Wrap {
+                            cursor.apply(&self.rope, Movement::Begin);
+                            continue;
+                        }
+                    }
+                    cursor.apply(&self.rope, Movement::Right(1));
+                }
+                self.bell |= edge && self.options.edge == Edge::Bell;
+                true
+            }
+            Key::Home => {
+                for cursor in &mut self.cursors {
+                    cursor.apply(&self.rope, Movement::Begin);
+                }
+                true
+            }
+            Key::End => {
+                for cursor in &mut self.cursors {
+                    cursor.apply(&self.rope, Movement::End);
+                }
+                true
+            }
+            Key::PageUp => {
+                let page = if self.options.page_size > 0 { self.options.page_size } else { height };
+                for cursor in &mut self.cursors {
+                    cursor.apply(&self.rope, Movement::Up(page));
+                }
+                true
+            }
+            Key::PageDown => {
+                let page = if self.options.page_size > 0 { self.options.page_size } else { height };
+                for cursor in &mut self.cursors {
+                    cursor.apply(&self.rope, Movement::Down(page));
+                }
+                true
+            }
+            // Half a page, with the view following so the cursor stays on the same row
+            Key::Ctrl('u') | Key::Ctrl('d') => {
+                let line = self.line();
+                let half = (height / 2).max(1);
+                for cursor in &mut self.cursors {
+                    cursor.apply(&self.rope, if key == Key::Ctrl('u') { Movement::Up(half) } else { Movement::Down(half) });
+                }
+                self.scrolled += self.line() as isize - line as isize;
+                true
+            }
+            Key::Ctrl('a') => {
+                for cursor in &mut self.cursors {
+                    cursor.apply(&self.rope, Movement::LineBegin);
+                }
+                true
+            }
+            Key::Ctrl('e') => {
+                for cursor in &mut self.cursors {
+                    cursor.apply(&self.rope, Movement::LineEnd);
+                }
+                true
+            }
+            // With smart indent, a break after an opening bracket indents a level more, and one
+            // between brackets also moves the closing bracket to a line of its own
+            Key::Char('\n') if self.options.auto_indent && !self.paste_mode => {
+                let smart = self.options.smart_indent;
+                let level = if self.options.soft_tabs { " ".repeat(self.options.indent_width()) } else { "\t".to_string() };
+                let inserted = self.edit(|rope, cursor| {
+                    cursor.apply(rope, Movement::GotoCol(cursor.col(rope)));
+                    let (line, col) = (rope.line(cursor.line), cursor.col(rope));
+                    let indent = indentation(line).chars().take(col).collect::<String>();
+                    let before = line.slice(..col).chars().filter(|c| !c.is_whitespace()).last();
+                    let after = line.slice(col..columns(line)).chars().find(|c| !c.is_whitespace());
+                    let open = before.filter(|&c| smart && "{[(".contains(c));
+                    let inner = if open.is_some() { format!("{}{}", indent, level) } else { indent.clone() };
+                    let text = match open {
+                        Some(open) if after == Some(closing(open)) => format!("\n{}\n{}", inner, indent),
+                        _ => format!("\n{}", inner),
+                    };
+                    let pos = cursor.pos(rope);
+                    rope.insert(pos, &text);
+                    cursor.apply(rope, Movement::Goto(rope.char_to_line(pos) + 1, inner.chars().count()));
+                    true
+                });
+                if inserted { self.changed(Change::Insert('\n'.to_string())); }
+                self.indented = inserted;
+                true
+            }
+            Key::Char('\t') if self.options.soft_tabs => {
+                let (width, tab_width) = (self.options.indent_width(), self.options.tab_width);
+                let inserted = self.edit(|rope, cursor| {
+                    cursor.apply(rope, Movement::GotoCol(cursor.col(rope)));
+                    // The stop is counted in cells, tabs before the cursor taking up to theirs
+                    let n = width - cells(rope.line(cursor.line).slice(..cursor.col(rope)), 0, tab_width) % width;
+                    rope.insert(cursor.pos(rope), &" ".repeat(n));
+                    cursor.apply(rope, Movement::Right(n));
+                    true
+                });
+                if inserted { self.changed(Change::Insert('\t'.to_string())); }
+                true
+            }
+            // A closing bracket or quote typed right before the same one, as auto-pairs leave
+            // them, moves over it at every cursor
+            Key::Char(c @ ('}' | ']' | ')' | '"')) if self.pairing() && self.cursors.iter().all(|cursor| {
+                let pos = cursor.pos(&self.rope);
+                cursor.anchor.is_none() && pos < self.rope.len_chars() && self.rope.char(pos) == c
+            }) => {
+                for cursor in &mut self.cursors {
+                    cursor.apply(&self.rope, Movement::Right(1));
+                }
+                true
+            }
+            // With auto-pairs, the closing bracket or quote goes after the cursor along with the
+            // opening one, only before whitespace, a closing bracket or the end of the line, and
+            // for a quote not right after a word, which it would more likely end
+            Key::Char(c @ ('{' | '[' | '(' | '"')) if self.pairing() => {
+                let inserted = self.edit(|rope, cursor| {
+                    cursor.apply(rope, Movement::GotoCol(cursor.col(rope)));
+                    let pos = cursor.pos(rope);
+                    let next = if pos < rope.len_chars() { rope.char(pos) } else { '\n' };
+                    let word = pos > 0 && rope.char(pos - 1).is_alphanumeric();
+                    let paired = (next.is_whitespace() || matches!(next, '}' | ']' | ')')) && !(c == '"' && word);
+                    if paired {
+                        rope.insert(pos, &format!("{}{}", c, closing(c)));
+                    } else {
+                        rope.insert_char(pos, c);
+                    }
+                    cursor.apply(rope, Movement::Right(1));
+                    true
+                });
+                if inserted { self.changed(Change::Insert(c.to_string())); }
+                true
+            }
+            // A closing bracket typed in the indentation takes a level of it away, the
+            // opening one having added it
+            Key::Char(c @ ('}' | ']' | ')')) if self.options.smart_indent && !self.paste_mode && !self.overwrite => {
+                let width = self.options.indent_width();
+                let inserted = self.edit(|rope, cursor| {
+                    cursor.apply(rope, Movement::GotoCol(cursor.col(rope)));
+                    let (line, col) = (rope.line(cursor.line), cursor.col(rope));
+                    if col > 0 && line.slice(..col).chars().all(|c| c == ' ' || c == '\t') {
+                        let n = soft_tab(line, col, width);
+                        let pos = cursor.pos(rope);
+                        rope.remove(pos - n..pos);
+                        cursor.apply(rope, Movement::GotoCol(col - n));
+                    }
+                    rope.insert_char(cursor.pos(rope), c);
+                    cursor.apply(rope, Movement::Right(1));
+                    true
+                });
+                if inserted { self.changed(Change::Insert(c.to_string())); }
+                true
+            }
+            Key::Char(c) => {
+                // Line breaks are inserted even when overwriting
+                let overwrite = self.overwrite && c != '\n';
+                let inserted = self.edit(|rope, cursor| {
+                    // Drop the column kept from a longer line so it doesn't move past the new character
+                    cursor.apply(rope, Movement::GotoCol(cursor.col(rope)));
+                    let pos = cursor.pos(rope);
+                    if overwrite && cursor.col(rope) < cursor.columns(rope) {
+                        rope.remove(pos..pos + 1);
+                    }
+                    rope.insert_char(pos, c);
+                    cursor.apply(rope, Movement::Right(1));
+                    true
+                });
+                if inserted { self.changed(Change::Insert(c.to_string())); }
+                true
+            }
+            // Right after a line break, the indentation given to the new line goes at once
+            Key::Backspace => {
+                let (soft_tabs, width) = (self.options.soft_tabs, self.options.indent_width());
+                let indented = mem::take(&mut self.indented) && self.options.backspace_indent;
+                let pairing = self.pairing();
+                let removed = self.edit(|rope, cursor| {
+                    if cursor.remove_selection(rope) {
+                        return true;
+                    }
+                    let pos = cursor.pos(rope);
+                    if pos == 0 {
+                        return false;
+                    }
+                    // Between brackets or quotes with nothing in them, both go
+                    let opening = rope.char(pos - 1);
+                    if pairing && matches!(opening, '{' | '[' | '(' | '"') && pos < rope.len_chars() && rope.char(pos) == closing(opening) {
+                        cursor.apply(rope, Movement::Left(1));
+                        rope.remove(pos - 1..pos + 1);
+                        return true;
+                    }
+                    let (line, col) = (rope.line(cursor.line), cursor.col(rope));
+                    let n = if indented && col > 0 && line.slice(..col).chars().all(|c| c == ' ' || c == '\t') {
+                        col
+                    } else if soft_tabs {
+                        soft_tab(line, col, width)
+                    } else {
+                        1
+                    };
+                    cursor.apply(rope, Movement::Left(n));
+                    rope.remove(pos - n..pos);
+                    true
+                });
+                if removed { self.changed(Change::Backspace(1)); }
+                true
+            }
+            Key::Delete => {
+                let removed = self.edit(|rope, cursor| {
+                    if cursor.remove_selection(rope) {
+                        return true;
+                    }
+                    if cursor.pos(rope) >= end(rope) {
+                        return false;
+                    }
+                    cursor.apply(rope, Movement::GotoCol(cursor.col(rope)));
+                    let pos = cursor.pos(rope);
+                    rope.remove(pos..pos + 1);
+                    true
+                });
+                if removed { self.changed(Change::Delete(1)); }
+                true
+            }
+            // Breaks the line with the cursors staying before the break
+            Key::Ctrl('o') => {
+                let split = self.edit(|rope, cursor| {
+                    cursor.apply(rope, Movement::GotoCol(cursor.col(rope)));
+                    rope.insert_char(cursor.pos(rope), '\n');
+                    true
+                });
+                if split { self.changed(Change::Split(1)); }
+                true
+            }
+            // Opens an empty line below or above the line of each cursor, which moves to it.
+            // Alt-O cannot be told from the start of a function key sequence.
+            Key::Alt('o') | Key::Alt('u') => {
+                let above = key == Key::Alt('u');
+                let indent = self.options.auto_indent && !self.paste_mode;
+                for cursor in &mut self.cursors {
+                    cursor.apply(&self.rope, Movement::GotoCol(if above { 0 } else { cursor.columns(&self.rope) }));
+                }
+                let opened = self.edit(|rope, cursor| {
+                    let indent = if indent { indentation(rope.line(cursor.line)) } else { String::new() };
+                    let line = if above {
+                        rope.insert(cursor.pos(rope), &format!("{}\n", indent));
+                        cursor.line
+                    } else {
+                        rope.insert(cursor.pos(rope), &format!("\n{}", indent));
+                        cursor.line + 1
+                    };
+                    cursor.apply(rope, Movement::Goto(line, indent.chars().count()));
+                    true
+                });
+                if opened { self.changed(Change::Open { above, count: 1 }); }
+                true
+            }
+            // Inserts a copy of each selection after it, which then selects the copy, or without
+            // a selection a copy of the line below it, which the cursor moves to
+            Key::Alt('d') => {
+                let mut copies = Vec::with_capacity(self.cursors.len());
+                let duplicated = self.edit(|rope, cursor| {
+                    match cursor.selection(rope).filter(|(start, end)| start < end) {
+                        Some((start, end)) => {
+                            let text = rope.slice(start..end).to_string();
+                            rope.insert(end, &text);
+                            let pos = end + (end - start);
+                            let line = rope.char_to_line(pos);
+                            cursor.apply(rope, Movement::Goto(line, pos - rope.line_to_char(line)));
+                            copies.push(end - start);
+                        }
+                        None => {
+                            let line = rope.line(cursor.line);
+                            let text = format!("\n{}", line.slice(..columns(line)));
+                            let col = cursor.col(rope);
+                            rope.insert(rope.line_to_char(cursor.line) + columns(line), &text);
+                            cursor.apply(rope, Movement::Goto(cursor.line + 1, col));
+                            copies.push(0);
+                        }
+                    }
+                    true
+                });
+                if duplicated {
+                    // Edits drop the selections, the copies are selected once all are made
+                    if copies.len() == self.cursors.len() {
+                        for (cursor, n) in self.cursors.iter_mut().zip(copies).filter(|&(_, n)| n > 0) {
+                            let pos = cursor.pos(&self.rope) - n;
+                            let line = self.rope.char_to_line(pos);
+                            cursor.anchor = Some((line, pos - self.rope.line_to_char(line)));
+                        }
+                    }
+                    self.changed(Change::Duplicate(1));
+                }
+                true
+            }
+            // Toggles the case of the character under each cursor, moving past it, or of each
+            // selection, which stays over the text whose length the case may change
+            Key::Alt('~') => {
+                let mut lengths = Vec::with_capacity(self.cursors.len());
+                let toggled = self.edit(|rope, cursor| {
+                    cursor.apply(rope, Movement::GotoCol(cursor.col(rope)));
+                    let selection = cursor.selection(rope).filter(|(start, end)| start < end);
+                    let (start, end) = match selection {
+                        Some(range) => range,
+                        None if cursor.col(rope) < cursor.columns(rope) => (cursor.pos(rope), cursor.pos(rope) + 1),
+                        None => return false,
+                    };
+                    let text = rope.slice(start..end).chars().flat_map(|c| {
+                        if c.is_lowercase() { c.to_uppercase().collect::<Vec<char>>() } else { c.to_lowercase().collect() }
+                    }).collect::<String>();
+                    rope.remove(start..end);
+                    rope.insert(start, &text);
+                    let pos = start + text.chars().count();
+                    let line = rope.char_to_line(pos);
+                    cursor.apply(rope, Movement::Goto(line, pos - rope.line_to_char(line)));
+                    let length = if selection.is_some() { text.chars().count() } else { 0 };
+                    lengths.push((length, rope.char_to_line(start), line));
+                    true
+                });
+                if toggled {
+                    // Edits drop the selections, they are selected again once all are toggled
+                    if lengths.len() == self.cursors.len() {
+                        for (cursor, &(n, _, _)) in self.cursors.iter_mut().zip(&lengths).filter(|&(_, &(n, _, _))| n > 0) {
+                            let pos = cursor.pos(&self.rope) - n;
+                            let line = self.rope.char_to_line(pos);
+                            cursor.anchor = Some((line, pos - self.rope.line_to_char(line)));
+                        }
+                    }
+                    // Only the line of each cursor is marked as it edits, not all those selected
+                    for (_, first, last) in lengths {
+                        self.touch(first, last - first + 1, last - first + 1);
+                    }
+                    self.changed(Change::Case(1));
+                }
+                true
+            }
+            // Ctrl-/ is read as Ctrl-7, the byte terminals send for both
+            Key::Alt('/') | Key::Ctrl('7') => {
+                if self.comment() {
+                    self.changed(Change::Comment);
+                }
+                true
+            }
+            // Selects from the bracket the cursor is on, or the innermost one around it, to the
+            // one matching it, with the brackets or only what they hold
+            Key::Alt('b') | Key::Alt('B') => {
+                let inside = key == Key::Alt('B');
+                let mut found = false;
+                for cursor in &mut self.cursors {
+                    let rope = &self.rope;
+                    if let Some((open, close)) = enclosing(rope, cursor.pos(rope)) {
+                        let (start, end) = if inside { (open + 1, close) } else { (open, close + 1) };
+                        let line = rope.char_to_line(start);
+                        cursor.anchor = Some((line, start - rope.line_to_char(line)));
+                        let line = rope.char_to_line(end);
+                        cursor.apply(rope, Movement::Goto(line, end - rope.line_to_char(line)));
+                        found = true;
+                    }
+                }
+                if !found {
+                    self.set_message("No brackets around the cursor");
+                }
+                true
+            }
+            // Exchanges the ends of the selections, so that moving changes the other end
+            Key::Alt('x') => {
+                for cursor in &mut self.cursors {
+                    if let Some((line, col)) = cursor.anchor {
+                        cursor.anchor = Some((cursor.line, cursor.col(&self.rope)));
+                        cursor.apply(&self.rope, Movement::Goto(line, col));
+                    }
+                }
+                true
+            }
+            Key::Alt('*') | Key::Alt('#') => {
+                self.search_word(key == Key::Alt('*'));
+                true
+            }
+            Key::Alt('m') | Key::Alt('M') => {
+                self.modification(key == Key::Alt('m'));
+                true
+            }
+            Key::Alt('e') => {
+                self.next_misspelling();
+                true
+            }
+            Key::Alt('E') => {
+                self.suggest();
+                true
+            }
+            Key::Alt('z') => {
+                self.fold();
+                true
+            }
+            Key::Alt('Z') => {
+                self.folds.clear();
+                true
+            }
+            Key::Alt('j') => {
+                if let Some(cursor) = self.cursors.first() {
+                    if cursor.line > 0 {
+                        self.cursors.insert(0, Cursor::new(cursor.line - 1, cursor.col));
+                    }
+                }
+                true
+            }
+            Key::Alt('k') => {
+                if let Some(cursor) = self.cursors.last() {
+                    if cursor.line < lines(&self.rope) {
+                        self.cursors.push(Cursor::new(cursor.line + 1, cursor.col));
+                    }
+                }
+                true
+            }
+            Key::Ctrl('k') => {
+                self.delete_lines();
+                true
+            }
+            Key::Ctrl('y') => {
+                if !self.register.is_empty() {
+                    // Lines are pasted above the line of each cursor, which moves down along with it.
+                    // Text that is not whole lines, as swapped in by Alt-y, is pasted at the cursor
+                    // instead, which moves past it.
+                    let whole = self.register.iter().all(|piece| piece.chars().last().is_some_and(is_break));
+                    if whole {
+                        for cursor in &mut self.cursors {
+                            cursor.apply(&self.rope, Movement::GotoCol(0));
+                        }
+                    }
+                    self.merge();
+                    let mut pieces = self.pieces().into_iter();
+                    let pasted = self.edit(|rope, cursor| {
+                        let text = pieces.next().unwrap_or_default();
+                        let pos = cursor.pos(rope);
+                        rope.insert(pos, &text);
+                        if whole {
+                            cursor.apply(rope, Movement::Down(Rope::from_str(&text).len_lines() - 1));
+                        } else {
+                            let end = pos + text.chars().count();
+                            let line = rope.char_to_line(end);
+                            cursor.apply(rope, Movement::Goto(line, end - rope.line_to_char(line)));
+                        }
+                        true
+                    });
+                    if pasted { self.changed(Change::Paste(1)); }
+                }
+                true
+            }
+            Key::Alt('y') => {
+                self.swap_register();
+                true
+            }
+            // The first pinned cursor is the one kept, if any, and it is unpinned
+            Key::Esc => {
+                if let Some(i) = self.cursors.iter().position(|cursor| cursor.pinned) {
+                    self.cursors.swap(0, i);
+                }
+                self.cursors.drain(1..);
+                for cursor in &mut self.cursors {
+                    cursor.anchor = None;
+                    cursor.pinned = false;
+                }
+                true
+            }
+            Key::Alt('t') => {
+                if let Some(cursor) = self.cursors.first_mut() {
+                    cursor.pinned = !cursor.pinned;
+                    let pinned = cursor.pinned;
+                    self.set_message(if pinned { "Cursor pinned" } else { "Cursor unpinned" });
+                }
+                true
+            }
+            Key::F(2) => {
+                self.paste_mode = !self.paste_mode;
+                true
+            }
+            Key::Insert => {
+                self.overwrite = !self.overwrite;
+                true
+            }
+            Key::Alt('q') => {
+                if let Some(keys) = self.recording.take() {
+                    self.recorded = keys;
+                } else {
+                    self.recording = Some(Vec::new());
+                }
+                true
+            }
+            Key::Alt('.') => {
+                if let Some(change) = self.change.clone() {
+                    match &change {
+                        Change::Insert(text) => for c in text.chars() { self.dispatch(Key::Char(c), height); },
+                        Change::Backspace(n) => for _ in 0..*n { self.dispatch(Key::Backspace, height); },
+                        Change::Delete(n) => for _ in 0..*n { self.dispatch(Key::Delete, height); },
+                        Change::DeleteLine(n) => for _ in 0..*n { self.dispatch(Key::Ctrl('k'), height); },
+                        Change::Paste(n) => for _ in 0..*n { self.dispatch(Key::Ctrl('y'), height); },
+                        Change::Split(n) => for _ in 0..*n { self.dispatch(Key::Ctrl('o'), height); },
+                        Change::Duplicate(n) => for _ in 0..*n { self.dispatch(Key::Alt('d'), height); },
+                        Change::Case(n) => for _ in 0..*n { self.dispatch(Key::Alt('~'), height); },
+                        Change::Comment => { self.dispatch(Key::Alt('/'), height); }
+                        Change::Swap => { self.dispatch(Key::Alt('y'), height); }
+                        Change::Open { above, count } => for _ in 0..*count {
+                            self.dispatch(Key::Alt(if *above { 'u' } else { 'o' }), height);
+                        },
+                    }
+                    self.change = Some(change);
+                }
+                self.run = false;
+                true
+            }
+            // Played back with the keys the buffer handles, a front end with keys of its own
+            // plays the macro back through them instead
+            Key::Alt('@') => {
+                let keys = self.recorded.clone();
+                for key in keys {
+                    self.key(key, height);
+                }
+                true
+            }
+            Key::Null => false,
+            _ => {
+                if self.options.show_unmapped {
+                    self.set_message(format!("Unmapped key: {:?}", key));
+                    true
+                } else { false }
+            }
+        }
+    }
+
+    // Text of the register for each cursor: with as many cursors as pieces, each cursor
+    // in order gets its own piece, otherwise all of them
+    fn pieces(&self) -> Vec<String> {
+        if self.register.len() == self.cursors.len() {
+            let mut pieces = vec![String::new(); self.cursors.len()];
+            for (piece, i) in self.register.iter().zip(self.order()) {
+                pieces[i] = piece.clone();
+            }
+            pieces
+        } else {
+            vec![self.register.concat(); self.cursors.len()]
+        }
+    }
+
+    // Indices of the cursors from the top of the buffer down
+    fn order(&self) -> Vec<usize> {
+        let mut order = (0..self.cursors.len()).collect::<Vec<usize>>();
+        order.sort_by_key(|&i| self.cursors[i].pos(&self.rope));
+        order
+    }
+
+    // Exchanges the selection of each cursor, or the character under it, with its text of the
+    // register, leaving the cursor at the start of it. The register gets what was replaced,
+    // a piece for each cursor in order.
+    fn swap_register(&mut self) {
+        if self.register.iter().all(String::is_empty) {
+            self.set_message("Register is empty");
+            return;
+        }
+        if !self.writable() {
+            return;
+        }
+        self.merge();
+        let order = self.order();
+        let mut pieces = self.pieces().into_iter();
+        let mut replaced = Vec::new();
+        let swapped = self.edit(|rope, cursor| {
+            let (start, end) = cursor.selection(rope).unwrap_or_else(|| {
+                let pos = cursor.pos(rope);
+                (pos, if cursor.col(rope) < columns(rope.line(cursor.line)) { pos + 1 } else { pos })
+            });
+            let text = pieces.next().unwrap_or_default();
+            replaced.push(rope.slice(start..end).to_string());
+            rope.remove(start..end);
+            rope.insert(start, &text);
+            let line = rope.char_to_line(start);
+            cursor.apply(rope, Movement::Goto(line, start - rope.line_to_char(line)));
+            true
+        });
+        if swapped {
+            self.register = order.into_iter().map(|i| mem::take(&mut replaced[i])).collect();
+            self.changed(Change::Swap);
+        }
+    }
+
+    // Deletes the lines of the cursors into the register, one piece per line, which
+    // consecutive deletions add to.
+    // The cursors move to the start of the line that follows, or precedes for the last line.
+    fn delete_lines(&mut self) {
+        if !self.writable() {
+            return;
+        }
+        let mut deleted = self.cursors.iter().map(|cursor| cursor.line).collect::<Vec<usize>>();
+        deleted.sort_unstable();
+        deleted.dedup();
+
+        let mut pieces = Vec::with_capacity(deleted.len());
+        // From the bottom up so the lines above keep their positions
+        for &line in deleted.iter().rev() {
+            let mut start = self.rope.line_to_char(line);
+            let end = if line < lines(&self.rope) { self.rope.line_to_char(line + 1) } else { self.rope.len_chars() };
+            let mut removed = self.rope.slice(start..end).to_string();
+            if line == lines(&self.rope) {
+                // The last line has no line break, remove the one before it instead
+                removed.push('\n');
+                start = if line > 0 { self.rope.line_to_char(line - 1) + columns(self.rope.line(line - 1)) } else { 0 };
+            }
+            pieces.insert(0, removed);
+            let count = self.rope.len_lines();
+            self.rope.remove(start..end);
+            // The line that takes the place of the deleted one is marked instead
+            let new = self.rope.len_lines();
+            self.touch(min(line, lines(&self.rope)), 1 + count.saturating_sub(new), 1 + new.saturating_sub(count));
+        }
+
+        for cursor in &mut self.cursors {
+            let line = cursor.line - deleted.iter().take_while(|&&line| line < cursor.line).count();
+            cursor.apply(&self.rope, Movement::Goto(line, 0));
+            cursor.anchor = None;
+        }
+        self.merge();
+
+        // Each cursor adds to its own piece while the number of cursors stays the same
+        if self.run && matches!(self.change, Some(Change::DeleteLine(_))) {
+            if self.register.len() == pieces.len() {
+                for (piece, more) in self.register.iter_mut().zip(pieces) {
+                    piece.push_str(&more);
+                }
+            } else {
+                self.register = vec![self.register.concat() + &pieces.concat()];
+            }
+        } else {
+            self.register = pieces;
+        }
+        self.changed(Change::DeleteLine(1));
+    }
+
+    // Line and column of the primary cursor. Like all the positions the editor takes and
+    // gives, both are 0-based, and columns count characters rather than cells.
+    pub fn primary_cursor(&self) -> (usize, usize) {
+        (self.line(), self.col())
+    }
+
+    // Start and end of the primary cursor's selection, if it has one
+    pub fn selection(&self) -> Option<((usize, usize), (usize, usize))> {
+        let (start, end) = self.cursors.first()?.selection(&self.rope)?;
+        let position = |pos: usize| {
+            let line = self.rope.char_to_line(pos);
+            (line, pos - self.rope.line_to_char(line))
+        };
+        Some((position(start), position(end)))
+    }
+
+    // Moves the primary cursor to a line and column, or as close as the buffer allows,
+    // dropping its selection
+    pub fn set_cursor(&mut self, line: usize, col: usize) -> bool {
+        self.indented = false;
+        if let Some(cursor) = self.cursors.first_mut() {
+            cursor.apply(&self.rope, Movement::Goto(line, col));
+            cursor.anchor = None;
+            return true;
+        }
+        false
+    }
+
+    // Replaces the cursors with ones at lines and columns, leaving out those on folded lines
+    pub fn set_cursors(&mut self, positions: &[(usize, usize)]) -> bool {
+        let cursors = positions.iter().filter(|&&(line, _)| !self.hidden(line)).map(|&(line, col)| {
+            let mut cursor = Cursor::new(0, 0);
+            cursor.apply(&self.rope, Movement::Goto(line, col));
+            cursor
+        }).collect::<Vec<Cursor>>();
+        if cursors.is_empty() {
+            return false;
+        }
+        self.indented = false;
+        self.cursors = cursors;
+        self.merge();
+        true
+    }
+
+    // Moves the primary cursor to a line and column, selecting from where it was
+    pub fn select(&mut self, line: usize, col: usize) -> bool {
+        if let Some(cursor) = self.cursors.first_mut() {
+            if cursor.anchor.is_none() {
+                cursor.anchor = Some((cursor.line, cursor.col(&self.rope)));
+            }
+            cursor.apply(&self.rope, Movement::Goto(line, col));
+            return true;
+        }
+        false
+    }
+
+    // Wraps the paragraph around the primary cursor, the lines between blank ones,
+    // to a text width keeping the indentation of its first line
+    fn reflow(&mut self, limit: usize) {
+        if !self.writable() {
+            return;
+        }
+        let rope = &self.rope;
+        let blank = |line: usize| rope.line(line).chars().take(columns(rope.line(line))).all(char::is_whitespace);
+        if blank(self.line()) {
+            return;
+        }
+        let (mut first, mut last) = (self.line(), self.line());
+        while first > 0 && !blank(first - 1) { first -= 1; }
+        while last < lines(rope) && !blank(last + 1) { last += 1; }
+
+        let text = (first..=last).map(|line| {
+            let line = rope.line(line);
+            line.slice(..columns(line)).to_string().trim().to_string()
+        }).collect::<Vec<String>>().join(" ");
+        let indent = indentation(rope.line(first));
+        let wrapped = wrap(&text, &indent, limit, !self.options.word_wrap).join("\n");
+
+        let start = rope.line_to_char(first);
+        let end = rope.line_to_char(last) + columns(rope.line(last));
+        self.rope.remove(start..end);
+        self.rope.insert(start, &wrapped);
+        self.touch(first, last - first + 1, wrapped.matches('\n').count() + 1);
+        self.dirty = true;
+        self.cursors.drain(1..);
+        self.set_cursor(first, indent.chars().count());
+    }
+
+    // Joins the lines of each cursor's selection, or its line and the next without one, into
+    // the first with a delimiter, their whitespace around it trimmed and blank lines left out
+    fn join(&mut self, delimiter: &str) {
+        let joined = self.edit(|rope, cursor| {
+            let (first, last) = if cursor.anchor.is_some() {
+                cursor.lines(rope)
+            } else {
+                (cursor.line, min(cursor.line + 1, lines(rope)))
+            };
+            if first == last {
+                return false;
+            }
+            let texts = (first..=last).map(|line| {
+                let line = rope.line(line);
+                line.slice(..columns(line)).to_string().trim().to_string()
+            }).filter(|text| !text.is_empty()).collect::<Vec<String>>();
+            let text = indentation(rope.line(first)) + &texts.join(delimiter);
+
+            let start = rope.line_to_char(first);
+            rope.remove(start..rope.line_to_char(last) + columns(rope.line(last)));
+            rope.insert(start, &text);
+            cursor.apply(rope, Movement::Goto(first, text.chars().count()));
+            true
+        });
+        if joined { self.dirty = true; }
+    }
+
+    // Rewrites the leading whitespace of every line in spaces, or in tabs followed by the
+    // spaces short of a tab stop, keeping the cursors on the same text
+    fn retab(&mut self) {
+        if !self.writable() {
+            return;
+        }
+        let tab_width = self.options.tab_width.max(1);
+        let mut count = 0;
+        for line in 0..self.rope.len_lines() {
+            let indent = indentation(self.rope.line(line));
+            let depth = indent.chars().fold(0, |depth, c| depth + advance(c, depth, tab_width));
+            let retabbed = if self.options.soft_tabs {
+                " ".repeat(depth)
+            } else {
+                format!("{}{}", "\t".repeat(depth / tab_width), " ".repeat(depth % tab_width))
+            };
+            if retabbed == indent {
+                continue;
+            }
+            let (old, new) = (indent.chars().count(), retabbed.chars().count());
+            let cols = self.cursors.iter().map(|cursor| cursor.col(&self.rope)).collect::<Vec<usize>>();
+            let start = self.rope.line_to_char(line);
+            self.rope.remove(start..start + old);
+            self.rope.insert(start, &retabbed);
+            for (cursor, col) in self.cursors.iter_mut().zip(cols).filter(|(cursor, _)| cursor.line == line) {
+                let col = if col >= old { col + new - old } else { min(col, new) };
+                cursor.apply(&self.rope, Movement::GotoCol(col));
+                cursor.anchor = None;
+            }
+            self.touch(line, 1, 1);
+            count += 1;
+        }
+        if count > 0 {
+            self.dirty = true;
+        }
+        self.set_message(format!("{} lines retabbed", count));
+    }
+
+    // Comments out the selections that start or end within a line with block comments, and
+    // otherwise the lines of the cursors with line comments, or uncomments them when they are
+    fn comment(&mut self) -> bool {
+        if !self.writable() {
+            return false;
+        }
+        let (line, block) = comments(self.extension().unwrap_or(""));
+        if line.is_none() && block.is_none() {
+            self.set_message("No comments known for this file type");
+            return false;
+        }
+        self.merge();
+        let rope = &self.rope;
+        let partial = self.cursors.iter().filter_map(|cursor| cursor.selection(rope)).any(|(start, end)| {
+            let (first, last) = (rope.char_to_line(start), rope.char_to_line(end));
+            let col = start - rope.line_to_char(first);
+            let whole = col <= indentation(rope.line(first)).chars().count()
+                && (end == rope.line_to_char(last) && last > first || end - rope.line_to_char(last) == columns(rope.line(last)));
+            start < end && !whole
+        });
+        match (block, line) {
+            (Some((open, close)), _) if partial => self.comment_blocks(open, close),
+            (_, Some(token)) => self.comment_lines(token, ""),
+            (Some((open, close)), None) => self.comment_lines(open, close),
+            (None, None) => false,
+        }
+    }
+
+    // Wraps each selection in `open` and `close`, or removes them from around or just inside
+    // it, leaving what is between them selected
+    fn comment_blocks(&mut self, open: &str, close: &str) -> bool {
+        let mut selected = Vec::with_capacity(self.cursors.len());
+        let (wrapped, spaced) = (format!("{} ", open), format!(" {}", close));
+        let commented = self.edit(|rope, cursor| {
+            let (start, end) = match cursor.selection(rope).filter(|(start, end)| start < end) {
+                Some(selection) => selection,
+                None => {
+                    selected.push(0);
+                    return false;
+                }
+            };
+            let text = rope.slice(start..end).to_string();
+            let before = rope.slice(start.saturating_sub(wrapped.chars().count())..start).to_string();
+            let after = rope.slice(end..min(end + spaced.chars().count(), rope.len_chars())).to_string();
+            // The longest delimiters present are removed, with the spaces next to them
+            let strip = |text: &str, front: &[&str], back: &[&str]| -> Option<(usize, usize)> {
+                let front = front.iter().find(|token| text.starts_with(*token))?;
+                let back = back.iter().find(|token| text[front.len()..].ends_with(*token))?;
+                Some((front.chars().count(), back.chars().count()))
+            };
+            let (start, end, inner) = if let Some((front, back)) = strip(&text, &[&wrapped, open], &[&spaced, close]) {
+                (start, end, text.chars().skip(front).take(text.chars().count() - front - back).collect::<String>())
+            } else if before.ends_with(open) && after.starts_with(close) {
+                let front = if before.ends_with(&wrapped) { wrapped.chars().count() } else { open.chars().count() };
+                let back = if after.starts_with(&spaced) { spaced.chars().count() } else { close.chars().count() };
+                (start - front, end + back, text)
+            } else {
+                (start, end, format!("{}{}{}", wrapped, text, spaced))
+            };
+            rope.remove(start..end);
+            rope.insert(start, &inner);
+            let pos = start + inner.chars().count();
+            let line = rope.char_to_line(pos);
+            cursor.apply(rope, Movement::Goto(line, pos - rope.line_to_char(line)));
+            selected.push(inner.chars().count());
+            true
+        });
+        // Edits drop the selections, what is left of them is selected once all are made
+        if commented && selected.len() == self.cursors.len() {
+            for (cursor, n) in self.cursors.iter_mut().zip(selected).filter(|&(_, n)| n > 0) {
+                let pos = cursor.pos(&self.rope) - n;
+                let line = self.rope.char_to_line(pos);
+                cursor.anchor = Some((line, pos - self.rope.line_to_char(line)));
+            }
+        }
+        commented
+    }
+
+    // Puts `open` at the indentation of the least indented of the lines of the cursors and
+    // `close` at their ends, or removes them when all the lines that are not blank have them
+    fn comment_lines(&mut self, open: &str, close: &str) -> bool {
+        let rope = &self.rope;
+        let mut lines = self.cursors.iter().flat_map(|cursor| {
+            let (first, last) = cursor.lines(rope);
+            first..=last
+        }).filter(|&line| rope.line(line).chars().take(columns(rope.line(line))).any(|c| !c.is_whitespace()))
+            .collect::<Vec<usize>>();
+        lines.sort_unstable();
+        lines.dedup();
+        if lines.is_empty() {
+            return false;
+        }
+
+        let texts = lines.iter().map(|&line| rope.line(line).slice(..columns(rope.line(line))).to_string()).collect::<Vec<String>>();
+        let commented = texts.iter().all(|text| text.trim_start().starts_with(open) && text.trim_end().ends_with(close));
+        let column = lines.iter().map(|&line| indentation(rope.line(line)).chars().count()).min().unwrap_or(0);
+        // From the last line up, as a line left empty after a lone carriage return joins it
+        for (&line, text) in lines.iter().zip(texts).rev() {
+            let count = self.rope.len_lines();
+            let indent = indentation(self.rope.line(line)).chars().count();
+            let start = self.rope.line_to_char(line);
+            let length = text.chars().count();
+            // Column the text after the front delimiter moves to and by how much
+            let (at, shift) = if commented {
+                let rest = &text.trim_start()[open.len()..];
+                let front = open.chars().count() + if rest.starts_with(' ') { 1 } else { 0 };
+                if !close.is_empty() {
+                    let body = text.trim_end();
+                    let body = &body[..body.len() - close.len()];
+                    // A space both after the front delimiter and before the back one goes with the front
+                    let back = close.chars().count() + if body.ends_with(' ') && body.len() > front + indent { 1 } else { 0 };
+                    let end = body.chars().count() + close.chars().count();
+                    self.rope.remove(start + end - back..start + end);
+                }
+                self.rope.remove(start + indent..start + indent + front);
+                (indent, -(front as isize))
+            } else {
+                if !close.is_empty() {
+                    let end = text.trim_end().chars().count();
+                    self.rope.insert(start + end, &format!(" {}", close));
+                }
+                self.rope.insert(start + column, &format!("{} ", open));
+                (column, open.chars().count() as isize + 1)
+            };
+            let max = columns(self.rope.line(min(line, self.rope.len_lines() - 1)));
+            // A selection taking the whole line in still does, its start in the indentation
+            // staying before the delimiters and its end at the end of the line staying there
+            let moved = |col: usize, start: Option<bool>| match start {
+                Some(true) if col <= indent => min(col, at),
+                Some(false) if col >= length => max,
+                _ if col >= at => min(max, (col as isize + shift).max(at as isize) as usize),
+                _ => col,
+            };
+            for cursor in self.cursors.iter_mut() {
+                let (start, anchored) = match cursor.anchor {
+                    Some(anchor) if anchor < (cursor.line, cursor.col) => (Some(false), Some(true)),
+                    Some(anchor) if anchor > (cursor.line, cursor.col) => (Some(true), Some(false)),
+                    _ => (None, None),
+                };
+                if cursor.line == line {
+                    cursor.col = moved(cursor.col, start);
+                }
+                if let Some((anchor, col)) = cursor.anchor {
+                    if anchor == line {
+                        cursor.anchor = Some((anchor, moved(col, anchored)));
+                    }
+                }
+            }
+            let new = self.rope.len_lines();
+            self.touch(line, 1 + count.saturating_sub(new), 1 + new.saturating_sub(count));
+        }
+        for cursor in self.cursors.iter_mut() {
+            let (line, col) = (cursor.line, cursor.col);
+            cursor.apply(&self.rope, Movement::Goto(line, col));
+        }
+        true
+    }
+
+    // Removes the spaces and tabs at the start, the end, or both of the lines of the cursors,
+    // leaving the lines of only those empty
+    fn trim(&mut self, start: bool, end: bool) {
+        if !self.writable() {
+            return;
+        }
+        let rope = &self.rope;
+        let mut lines = self.cursors.iter().flat_map(|cursor| {
+            let (first, last) = cursor.lines(rope);
+            first..=last
+        }).collect::<Vec<usize>>();
+        lines.sort_unstable();
+        lines.dedup();
+
+        for line in lines {
+            let text = self.rope.line(line).slice(..columns(self.rope.line(line))).to_string();
+            let len = text.chars().count();
+            let kept = text.trim_matches([' ', '\t']);
+            // Columns taken away before the text and where the text kept ends
+            let (lead, stop) = if kept.is_empty() {
+                (0, 0)
+            } else {
+                let lead = if start { text.chars().take_while(|&c| c == ' ' || c == '\t').count() } else { 0 };
+                let stop = if end { len - text.chars().rev().take_while(|&c| c == ' ' || c == '\t').count() } else { len };
+                (lead, stop)
+            };
+            if lead == 0 && stop == len {
+                continue;
+            }
+            let from = self.rope.line_to_char(line);
+            self.rope.remove(from + stop..from + len);
+            self.rope.remove(from..from + lead);
+            let moved = |col: usize| min(col, stop).saturating_sub(lead);
+            for cursor in self.cursors.iter_mut() {
+                if cursor.line == line {
+                    cursor.col = moved(cursor.col);
+                }
+                if let Some((anchor, col)) = cursor.anchor {
+                    if anchor == line {
+                        cursor.anchor = Some((anchor, moved(col)));
+                    }
+                }
+            }
+            self.touch(line, 1, 1);
+            self.dirty = true;
+        }
+        self.merge();
+    }
+
+    // Pads the cursors with spaces so they line up at the column of the rightmost one,
+    // after moving each to the next `target` character on its line when given
+    fn align(&mut self, target: Option<char>) {
+        if let Some(target) = target {
+            for cursor in &mut self.cursors {
+                let line = self.rope.line(cursor.line);
+                let col = cursor.col(&self.rope);
+                if let Some(i) = line.slice(col..columns(line)).chars().position(|c| c == target) {
+                    cursor.apply(&self.rope, Movement::GotoCol(col + i));
+                }
+            }
+        }
+        let column = self.cursors.iter().map(|cursor| cursor.col(&self.rope)).max().unwrap_or(0);
+        let aligned = self.edit(|rope, cursor| {
+            let n = column.saturating_sub(cursor.col(rope));
+            if n == 0 {
+                return false;
+            }
+            rope.insert(cursor.pos(rope), &" ".repeat(n));
+            cursor.apply(rope, Movement::GotoCol(column));
+            true
+        });
+        if aligned { self.dirty = true; }
+    }
+
+    // Moves the primary cursor to the next or previous whole word occurrence of the word
+    // under it, wrapping around the buffer, and makes that word the one searched for
+    fn search_word(&mut self, forward: bool) {
+        let is_word = |c| self.options.is_word(c);
+        let word = self.token(is_word);
+        if word.is_empty() || !word.chars().all(is_word) {
+            self.set_message("No word under the cursor");
+            return;
+        }
+        let (line, col) = (self.line(), self.col());
+        let slice = self.rope.line(line);
+        let mut start = col;
+        while start > 0 && is_word(slice.char(start - 1)) {
+            start -= 1;
+        }
+
+        let rope = &self.rope;
+        let at = |line: usize| {
+            let slice = rope.line(line);
+            occurrences(&slice.slice(..columns(slice)).to_string(), &word, is_word)
+        };
+        let last = lines(rope);
+        // Past the word on its line, then on the following lines, and last from the other end
+        let (found, wrapped) = if forward {
+            match at(line).into_iter().find(|&c| c > start).map(|c| (line, c))
+                .or_else(|| (line + 1..=last).find_map(|ln| at(ln).first().map(|&c| (ln, c)))) {
+                Some(found) => (Some(found), false),
+                None => ((0..=line).find_map(|ln| at(ln).first().map(|&c| (ln, c))), true),
+            }
+        } else {
+            match at(line).into_iter().rev().find(|&c| c < start).map(|c| (line, c))
+                .or_else(|| (0..line).rev().find_map(|ln| at(ln).last().map(|&c| (ln, c)))) {
+                Some(found) => (Some(found), false),
+                None => ((line..=last).rev().find_map(|ln| at(ln).last().map(|&c| (ln, c))), true),
+            }
+        };
+        if let Some((line, col)) = found {
+            if wrapped {
+                self.set_message(if forward { "Search wrapped to the top" } else { "Search wrapped to the bottom" });
+            }
+            self.jump(line, col);
+        }
+        self.search = Some(word);
+    }
+
+    // Moves the ends of the selections to word boundaries, out to take in the words
+    // they cut through, or in to leave them out
+    fn words(&mut self, shrink: bool) {
+        let (rope, options) = (&self.rope, &self.options);
+        let inside = |pos: usize| {
+            pos > 0 && pos < rope.len_chars() && options.is_word(rope.char(pos - 1)) && options.is_word(rope.char(pos))
+        };
+        for cursor in &mut self.cursors {
+            let (mut start, mut end) = match cursor.selection(rope) { Some(selection) => selection, None => continue };
+            let forward = cursor.pos(rope) == end;
+            if shrink {
+                while start < end && inside(start) { start += 1; }
+                while end > start && inside(end) { end -= 1; }
+            } else {
+                while inside(start) { start -= 1; }
+                while inside(end) { end += 1; }
+            }
+
+            let (anchor, pos) = if forward { (start, end) } else { (end, start) };
+            let line = rope.char_to_line(anchor);
+            cursor.anchor = if start < end { Some((line, anchor - rope.line_to_char(line))) } else { None };
+            let line = rope.char_to_line(pos);
+            cursor.apply(rope, Movement::Goto(line, pos - rope.line_to_char(line)));
+        }
+    }
+
+    // Moves the primary cursor like `set_cursor`, and flashes the line it lands on for the
+    // eye to find it after a jump further than the keys move
+    fn jump(&mut self, line: usize, col: usize) {
+        if self.set_cursor(line, col) {
+            self.flash = Some((self.line(), Instant::now()));
+        }
+    }
+
+    pub fn gotoline(&mut self, line: usize) {
+        let col = self.cursors.first().map_or(0, |cursor| cursor.col);
+        self.jump(line, col);
+    }
+
+    pub fn gotopercent(&mut self, percent: usize) {
+        self.gotoline(min(percent, 100) * lines(&self.rope) / 100);
+    }
+
+    // Goes to the character a 0-based byte offset falls in, as reported by tools that count
+    // bytes, or to the end of the buffer past it
+    pub fn goto_byte(&mut self, offset: usize) {
+        let pos = self.rope.byte_to_char(min(offset, self.rope.len_bytes()));
+        let line = self.rope.char_to_line(pos);
+        self.jump(line, pos - self.rope.line_to_char(line));
+    }
+
+    // Number of replacements a substitution would make, counted without copying the buffer
+    // when it replaces all of the occurrences in it
+    pub fn substitutions(&self, pattern: &str, global: bool, all: bool) -> usize {
+        if global && all {
+            return count_matches(&self.rope, pattern, false);
+        }
+        let lines = if all { 0..lines(&self.rope) + 1 } else { self.line()..self.line() + 1 };
+        lines.map(|line| {
+            let slice = self.rope.line(line);
+            let text = slice.slice(..columns(slice)).to_string();
+            if global { text.matches(pattern).count() } else if text.contains(pattern) { 1 } else { 0 }
+        }).sum()
+    }
+
+    // Replaces `pattern` on a line, returns the number of replacements
+    fn substitute(&mut self, line: usize, pattern: &str, replacement: &str, global: bool) -> usize {
+        let slice = self.rope.line(line);
+        let text = slice.slice(..columns(slice)).to_string();
+        let count = if global { text.matches(pattern).count() } else if text.contains(pattern) { 1 } else { 0 };
+        if count > 0 {
+            let replaced = if global { text.replace(pattern, replacement) } else { text.replacen(pattern, replacement, 1) };
+            let start = self.rope.line_to_char(line);
+            let lines = self.rope.len_lines();
+            self.rope.remove(start..start + text.chars().count());
+            self.rope.insert(start, &replaced);
+            let new = self.rope.len_lines();
+            self.touch(line, 1 + lines.saturating_sub(new), 1 + new.saturating_sub(lines));
+            self.dirty = true;
+        }
+        count
+    }
+
+    pub fn set(&mut self, option: &str) -> Result<(), String> {
+        let (name, value) = match option.find('=') {
+            Some(i) => (&option[..i], Some(&option[i + 1..])),
+            None => (option, None),
+        };
+        let (name, enable) = match name.strip_prefix("no") { Some(name) => (name, false), None => (name, true) };
+        self.revision += 1;
+        match (name, value) {
+            ("tabwidth", Some(value)) => match value.parse::<usize>() {
+                Ok(width) if width > 0 => self.options.tab_width = width,
+                _ => return Err(format!("Invalid tab width: {}", value)),
+            },
+            ("indentwidth", Some(value)) => match value.parse::<usize>() {
+                Ok(width) => self.options.indent_width = width,
+                _ => return Err(format!("Invalid indent width: {}", value)),
+            },
+            ("pagesize", Some(value)) => match value.parse::<usize>() {
+                Ok(size) => self.options.page_size = size,
+                _ => return Err(format!("Invalid page size: {}", value)),
+            },
+            ("scrolloff", Some(value)) => match value.parse::<usize>() {
+                Ok(lines) => self.options.scroll_off = lines,
+                _ => return Err(format!("Invalid scroll offset: {}", value)),
+            },
+            ("cursorlimit", Some(value)) => match value.parse::<usize>() {
+                Ok(limit) => self.options.cursor_limit = limit,
+                _ => return Err(format!("Invalid cursor limit: {}", value)),
+            },
+            ("maxlinelength", Some(value)) => match value.parse::<usize>() {
+                Ok(cells) => self.options.max_line_length = cells,
+                _ => return Err(format!("Invalid line length: {}", value)),
+            },
+            ("softtabs", None) | ("expandtab", None) => self.options.soft_tabs = enable,
+            ("autoindent", None) => self.options.auto_indent = enable,
+            ("backspaceindent", None) => self.options.backspace_indent = enable,
+            ("smartindent", None) => self.options.smart_indent = enable,
+            ("autopairs", None) => self.options.auto_pairs = enable,
+            ("paste", None) => self.paste_mode = enable,
+            ("showunmapped", None) => self.options.show_unmapped = enable,
+            ("indentguides", None) => self.options.indent_guides = enable,
+            ("desiredcolumn", None) => self.options.desired_column = enable,
+            ("guttermarks", None) => self.options.gutter_marks = enable,
+            ("selectnewline", None) => self.options.select_newline = enable,
+            ("minimap", None) => self.options.minimap = enable,
+            ("highlightword", None) => {
+                self.options.highlight_word = enable;
+                self.highlighted = None;
+                self.rest.1 = Some(Instant::now());
+            }
+            ("finalnewline", None) => self.options.final_newline = enable,
+            ("scrollpastend", None) => self.options.scroll_past_end = enable,
+            ("holdpinned", None) => self.options.hold_pinned = enable,
+            ("trimtrailing", None) => self.options.trim_trailing = enable,
+            ("lineending", Some(value)) => self.options.line_ending = match value {
+                "keep" => LineEnding::Keep,
+                "lf" => LineEnding::Lf,
+                "crlf" => LineEnding::CrLf,
+                "cr" => LineEnding::Cr,
+                _ => return Err(format!("Invalid line ending, keep, lf, crlf or cr: {}", value)),
+            },
+            ("wordwrap", None) => self.options.word_wrap = enable,
+            ("detectindent", None) => self.options.detect_indent = enable,
+            ("spell", None) => {
+                self.options.spell = enable;
+                if enable { self.read_dictionary()?; }
+            }
+            ("dictionary", Some(value)) => {
+                self.options.dictionary = value.to_string();
+                if self.options.spell { self.read_dictionary()?; }
+            }
+            ("dateformat", Some(value)) => self.options.date_format = value.to_string(),
+            // Spaces after the marks, or before and after them
+            ("gutterpadding", Some(value)) => {
+                let padding = match value.split_once(',') {
+                    Some((before, after)) => before.parse::<usize>().ok().zip(after.parse::<usize>().ok()),
+                    None => value.parse::<usize>().ok().map(|after| (0, after)),
+                };
+                match padding {
+                    Some(padding) => self.options.gutter_padding = padding,
+                    None => return Err(format!("Invalid gutter padding, N or N,N: {}", value)),
+                }
+            }
+            ("gutterseparator", Some(value)) => match value.chars().count() {
+                0 | 1 => self.options.gutter_separator = value.to_string(),
+                _ => return Err(format!("Invalid gutter separator, a single character: {}", value)),
+            },
+            // Named sets of characters, or the characters themselves
+            ("wordchars", Some(value)) => self.options.word_chars = match value {
+                "programming" => "_",
+                "prose" => "'-",
+                chars => chars,
+            }.to_string(),
+            ("edge", Some(value)) => self.options.edge = match value {
+                "stop" => Edge::Stop,
+                "bell" => Edge::Bell,
+                "wrap" => Edge::Wrap,
+                _ => return Err(format!("Invalid edge, stop, bell or wrap: {}", value)),
+            },
+            ("textwidth", Some(value)) => match value.parse::<usize>() {
+                Ok(width) if width > 0 => self.options.text_width = width,
+                _ => return Err(format!("Invalid text width: {}", value)),
+            },
+            _ => return Err(format!("Unknown option: {}", option)),
+        }
+        Ok(())
+    }
+
+    fn read_dictionary(&mut self) -> Result<(), String> {
+        match fs::read_to_string(&self.options.dictionary) {
+            Ok(text) => {
+                self.words = text.lines().map(|word| word.trim().to_lowercase()).filter(|word| !word.is_empty()).collect();
+                Ok(())
+            }
+            Err(err) => {
+                self.options.spell = false;
+                Err(format!("Cannot read dictionary \"{}\": {}", self.options.dictionary, err))
+            }
+        }
+    }
+
+    // Start and end columns of the occurrences on a line of the word the cursor rests on
+    pub fn highlights_at(&self, line: usize) -> Vec<(usize, usize)> {
+        let word = match &self.highlighted {
+            Some(word) => word,
+            None => return Vec::new(),
+        };
+        let slice = self.rope.line(line);
+        let length = word.chars().count();
+        occurrences(&slice.slice(..columns(slice)).to_string(), word, |c| self.options.is_word(c))
+            .into_iter().map(|start| (start, start + length)).collect()
+    }
+
+    // Ranges of columns of the words on a line missing from the dictionary, when spell
+    // checking. Names in code like snake_case, camelCase or CONSTANTS and words with digits
+    // are left alone, as are single letters.
+    pub fn misspelled(&self, line: usize) -> Vec<(usize, usize)> {
+        if !self.options.spell {
+            return Vec::new();
+        }
+        let line = self.rope.line(line);
+        let chars = line.chars().take(columns(line)).collect::<Vec<char>>();
+        let mut misspelled = Vec::new();
+        let mut start = 0;
+        while start < chars.len() {
+            if !self.options.is_word(chars[start]) {
+                start += 1;
+                continue;
+            }
+            let end = start + chars[start..].iter().take_while(|&&c| self.options.is_word(c)).count();
+            let token = &chars[start..end];
+            let code = token.iter().any(|&c| !c.is_alphabetic()) || token[1..].iter().any(|c| c.is_uppercase());
+            if token.len() > 1 && !code && !self.words.contains(&token.iter().collect::<String>().to_lowercase()) {
+                misspelled.push((start, end));
+            }
+            start = end;
+        }
+        misspelled
+    }
+
+    // Moves the primary cursor to the next word missing from the dictionary, wrapping around
+    fn next_misspelling(&mut self) {
+        if !self.options.spell {
+            self.set_message("Spell checking is off, :set spell turns it on");
+            return;
+        }
+        let (line, col) = (self.line(), self.col());
+        let last = lines(&self.rope);
+        let found = self.misspelled(line).into_iter().find(|&(start, _)| start > col).map(|(start, _)| (line, start))
+            .or_else(|| (line + 1..=last).chain(0..=line).find_map(|ln| self.misspelled(ln).first().map(|&(start, _)| (ln, start))));
+        match found {
+            Some((line, col)) => self.jump(line, col),
+            None => self.set_message("No misspelled words"),
+        }
+    }
+
+    // Tells the words of the dictionary one edit away from the word under the primary cursor
+    fn suggest(&mut self) {
+        let word = self.token(|c| self.options.is_word(c)).to_lowercase();
+        if word.is_empty() || !self.options.spell {
+            return;
+        }
+        if self.words.contains(&word) {
+            self.set_message(format!("\"{}\" is in the dictionary", word));
+            return;
+        }
+        let chars = word.chars().collect::<Vec<char>>();
+        let letters = ('a'..='z').chain(chars.iter().copied()).collect::<HashSet<char>>();
+        let mut candidates = Vec::new();
+        for i in 0..=chars.len() {
+            if i < chars.len() {
+                candidates.push([&chars[..i], &chars[i + 1..]].concat());
+            }
+            if i + 1 < chars.len() {
+                candidates.push([&chars[..i], &[chars[i + 1], chars[i]], &chars[i + 2..]].concat());
+            }
+            for &c in &letters {
+                if i < chars.len() && c != chars[i] {
+                    candidates.push([&chars[..i], &[c], &chars[i + 1..]].concat());
+                }
+                candidates.push([&chars[..i], &[c], &chars[i..]].concat());
+            }
+        }
+        let mut suggestions = candidates.into_iter().map(|chars| chars.into_iter().collect::<String>())
+            .filter(|candidate| self.words.contains(candidate)).collect::<Vec<String>>();
+        suggestions.sort();
+        suggestions.dedup();
+        if suggestions.is_empty() {
+            self.set_message(format!("No suggestions for \"{}\"", word));
+        } else {
+            suggestions.truncate(8);
+            self.set_message(format!("{}: {}", word, suggestions.join(", ")));
+        }
+    }
+
+    pub fn settings(&self) -> String {
+        let flags = [
+            ("softtabs", self.options.soft_tabs),
+            ("autoindent", self.options.auto_indent),
+            ("backspaceindent", self.options.backspace_indent),
+            ("smartindent", self.options.smart_indent),
+            ("autopairs", self.options.auto_pairs),
+            ("paste", self.paste_mode),
+            ("showunmapped", self.options.show_unmapped),
+            ("indentguides", self.options.indent_guides),
+            ("desiredcolumn", self.options.desired_column),
+            ("guttermarks", self.options.gutter_marks),
+            ("selectnewline", self.options.select_newline),
+            ("minimap", self.options.minimap),
+            ("highlightword", self.options.highlight_word),
+            ("finalnewline", self.options.final_newline),
+            ("scrollpastend", self.options.scroll_past_end),
+            ("holdpinned", self.options.hold_pinned),
+            ("trimtrailing", self.options.trim_trailing),
+            ("wordwrap", self.options.word_wrap),
+            ("detectindent", self.options.detect_indent),
+            ("spell", self.options.spell),
+        ];
+        let values = format!("tabwidth={} indentwidth={} textwidth={} pagesize={} scrolloff={} cursorlimit={} maxlinelength={} dictionary={} wordchars={} dateformat={} gutterpadding={},{} gutterseparator={} edge={} lineending={}",
+                             self.options.tab_width, self.options.indent_width, self.options.text_width, self.options.page_size,
+                             self.options.scroll_off, self.options.cursor_limit, self.options.max_line_length, self.options.dictionary, self.options.word_chars,
+                             self.options.date_format, self.options.gutter_padding.0, self.options.gutter_padding.1,
+                             self.options.gutter_separator, format!("{:?}", self.options.edge).to_lowercase(),
+                             format!("{:?}", self.options.line_ending).to_lowercase());
+        flags.iter().fold(values, |settings, (name, enabled)| {
+            format!("{} {}{}", settings, if *enabled { "" } else { "no" }, name)
+        })
+    }
+
+    // Quit commands are left to the caller, which knows about the other buffers
+    pub fn command(&mut self, command: &Command) {
+        let len = self.rope.len_lines();
+        self.execute(command);
+        if self.rope.len_lines() != len {
+            self.folds.clear();
+        }
+        // Folds open on the line jumped to
+        let line = self.line();
+        self.folds.retain(|&(first, last)| line < first || last < line);
+        self.skip(&[]);
+    }
+
+    fn execute(&mut self, command: &Command) {
+        match command {
+            Command::Write { filename, .. } => match filename {
+                Some(filename) if self.other_file(filename) => {
+                    self.write_as(filename);
+                }
+                Some(filename) => {
+                    self.filename = Some(filename.clone());
+                    self.save();
+                }
+                None if self.filename.is_some() => self.save(),
+                None => self.set_message("No file name"),
+            },
+            Command::Quit { .. } | Command::View => {}
+            Command::Revert { force } => self.revert(*force),
+            Command::Encoding(None) => self.set_message(format!("Encoding: {}", self.encoding.name())),
+            // The buffer differs from its file once it is to be saved in another encoding
+            Command::Encoding(Some(encoding)) => if *encoding != self.encoding {
+                self.encoding = *encoding;
+                self.dirty = true;
+            },
+            Command::OnSave { extension, command } => {
+                self.hooks.retain(|(ext, _)| ext != extension);
+                self.hooks.push((extension.clone(), command.clone()));
+            }
+            Command::For { extension, command } => {
+                if self.extension() == Some(extension.as_str()) {
+                    self.execute(command);
+                }
+            }
+            Command::Set(options) => {
+                if options.is_empty() {
+                    let settings = self.settings();
+                    self.set_message(settings);
+                }
+                for option in options {
+                    if let Err(err) = self.set(option) {
+                        self.set_message(err);
+                        break;
+                    }
+                }
+            }
+            Command::Goto(line) => self.gotoline(*line),
+            Command::Percent(percent) => self.gotopercent(*percent),
+            Command::Byte(offset) => self.goto_byte(*offset),
+            Command::Words { shrink } => self.words(*shrink),
+            Command::Align(target) => self.align(*target),
+            Command::Filter(command) => self.filter(command),
+            Command::Reflow(width) => self.reflow(width.unwrap_or(self.options.text_width)),
+            Command::Retab => self.retab(),
+            Command::Balance { close } => self.balance(*close),
+            Command::Trim { start, end } => self.trim(*start, *end),
+            Command::StripEscapes => self.strip_escapes(),
+            Command::Join(delimiter) => self.join(delimiter.as_deref().unwrap_or(" ")),
+            Command::Date(format) => self.date(&format.clone().unwrap_or_else(|| self.options.date_format.clone())),
+            Command::Substitute { .. } if !self.writable() => {}
+            Command::Substitute { pattern, replacement, global, all } => {
+                let lines = if *all { 0..lines(&self.rope) + 1 } else { self.line()..self.line() + 1 };
+                let count = lines.fold(0, |count, line| count + self.substitute(line, pattern, replacement, *global));
+                if count == 0 {
+                    self.set_message(format!("Pattern not found: {}", pattern));
+                } else if *all {
+                    self.set_message(format!("{} substitutions", count));
+                }
+            }
+        }
+    }
+
+    // Whether a file is another than the buffer's own, which writing it leaves as it is
+    pub fn other_file(&self, filename: &str) -> bool {
+        self.filename.is_some() && self.filename.as_deref() != Some(filename)
+    }
+
+    // Writes the buffer as it would be saved to another file, returns whether it was written
+    pub fn write_as(&mut self, filename: &str) -> bool {
+        let mut rope = self.rope.clone();
+        terminate(&mut rope, &self.options, |_, _, _| {});
+        match store(&rope, filename, self.encoding, &AtomicUsize::new(0)) {
+            Ok(()) => {
+                self.set_message(self.written(filename));
+                true
+            }
+            Err(err) => {
+                self.set_message(format!("Cannot save \"{}\": {}", filename, err));
+                false
+            }
+        }
+    }
+
+    pub fn write(&self, filename: &str) -> io::Result<()> {
+        store(&self.rope, filename, self.encoding, &AtomicUsize::new(0))
+    }
+
+    // Reads the file again, keeping the cursors on the same lines where possible. A file
+    // read as UTF-8 has its encoding found again, those in other encodings keep theirs.
+    // A buffer compared with another is not, its rows would no longer line up with the lines.
+    pub fn reload(&mut self) -> io::Result<()> {
+        if self.aligned.is_some() {
+            return Err(io::Error::other("the buffer is being compared with another"));
+        }
+        if let Some(filename) = self.filename.clone() {
+            let encoding = Some(self.encoding).filter(|&encoding| encoding != Encoding::Utf8);
+            let (rope, encoding, replaced) = read(&filename, encoding)?;
+            self.rope = rope;
+            self.revision += 1;
+            self.encoding = encoding;
+            self.decoded(&filename, replaced);
+            self.folds.clear();
+            self.modified.clear();
+            self.rediff();
+            for cursor in &mut self.cursors {
+                let (line, col) = (cursor.line, cursor.col);
+                cursor.anchor = None;
+                cursor.apply(&self.rope, Movement::Goto(line, col));
+            }
+            self.merge();
+            self.dirty = false;
+        }
+        Ok(())
+    }
+
+    // Throws the changes away, going back to the file as saved, or to an empty buffer
+    // without a file
+    fn revert(&mut self, force: bool) {
+        if self.dirty && !force {
+            self.set_message("No write since last change (add ! to override)");
+            return;
+        }
+        match self.filename.clone() {
+            Some(filename) => match self.reload() {
+                Ok(()) => self.set_message(format!("Reverted to \"{}\" as saved", filename)),
+                Err(err) => self.set_message(format!("Cannot reload \"{}\": {}", filename, err)),
+            },
+            None => {
+                self.rope = Rope::new();
+                self.revision += 1;
+                self.cursors = vec![Cursor::new(0, 0)];
+                self.folds.clear();
+                self.modified.clear();
+                self.dirty = false;
+            }
+        }
+    }
+
+    // Inserts the date and time at each cursor, moving past it, in a strftime format or one
+    // of the presets of `date_format`
+    fn date(&mut self, format: &str) {
+        if !self.writable() {
+            return;
+        }
+        let text = match formatted(&Local::now(), date_format(format)) {
+            Ok(text) => text,
+            Err(err) => return self.set_message(err),
+        };
+        let count = text.chars().count();
+        let inserted = self.edit(|rope, cursor| {
+            cursor.apply(rope, Movement::GotoCol(cursor.col(rope)));
+            rope.insert(cursor.pos(rope), &text);
+            cursor.apply(rope, Movement::Right(count));
+            true
+        });
+        if inserted { self.changed(Change::Insert(text)); }
+    }
+
+    // Runs a shell command on the primary cursor's selection, or the whole buffer without one,
+    // and replaces it with the output. A failing command leaves the buffer as it was.
+    fn filter(&mut self, command: &str) {
+        if !self.writable() {
+            return;
+        }
+        let (start, end) = self.cursors.first()
+            .and_then(|cursor| cursor.selection(&self.rope))
+            .filter(|(start, end)| start < end)
+            .unwrap_or((0, self.rope.len_chars()));
+        let input = self.rope.slice(start..end).to_string();
+
+        let child = process::Command::new("sh").arg("-c").arg(command)
+            .stdin(process::Stdio::piped())
+            .stdout(process::Stdio::piped())
+            .stderr(process::Stdio::piped())
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(err) => return self.set_message(format!("Cannot run {}: {}", command, err)),
+        };
+        // Written from another thread so that a command writing before it has read
+        // all its input cannot block on a full pipe
+        if let Some(mut stdin) = child.stdin.take() {
+            thread::spawn(move || stdin.write_all(input.as_bytes()));
+        }
+        let output = match child.wait_with_output() {
+            Ok(output) => output,
+            Err(err) => return self.set_message(format!("Cannot run {}: {}", command, err)),
+        };
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            match stderr.lines().find(|line| !line.trim().is_empty()) {
+                Some(stderr) => self.set_message(format!("{} failed: {}", command, stderr)),
+                None => self.set_message(format!("{} failed ({})", command, output.status)),
+            }
+            return;
+        }
+
+        let (line, col) = (self.line(), self.col());
+        let first = self.rope.char_to_line(start);
+        let removed = self.rope.char_to_line(end) - first + 1;
+        let count = self.rope.len_lines();
+        self.rope.remove(start..end);
+        self.rope.insert(start, &String::from_utf8_lossy(&output.stdout));
+        self.touch(first, removed, (removed + self.rope.len_lines()).saturating_sub(count));
+        self.dirty = true;
+        self.cursors.drain(1..);
+        if start == 0 && end == start + self.rope.len_chars() {
+            self.set_cursor(line, col);
+        } else {
+            let line = self.rope.char_to_line(start);
+            self.set_cursor(line, start - self.rope.line_to_char(line));
+        }
+    }
+
+    // Moves the primary cursor to the first bracket of its selection, or the buffer without
+    // one, left unmatched. With `close`, the brackets left open are closed at its end instead.
+    fn balance(&mut self, close: bool) {
+        let (start, end) = self.cursors.first()
+            .and_then(|cursor| cursor.selection(&self.rope))
+            .filter(|(start, end)| start < end)
+            .unwrap_or((0, self.rope.len_chars()));
+        let language = self.extension().map(comments).filter(|&(line, block)| line.is_some() || block.is_some());
+        let (offset, closers) = match unbalanced(&self.rope.slice(start..end).to_string(), language) {
+            Some(unbalanced) => unbalanced,
+            None => return self.set_message("Brackets balanced"),
+        };
+        if close && !closers.is_empty() {
+            if !self.writable() {
+                return;
+            }
+            let text = closers.iter().collect::<String>();
+            let line = self.rope.char_to_line(end);
+            self.rope.insert(end, &text);
+            self.touch(line, 1, 1);
+            self.dirty = true;
+            return self.set_message(format!("Closed with {}", text));
+        }
+        let pos = start + offset;
+        let line = self.rope.char_to_line(pos);
+        let bracket = self.rope.char(pos);
+        self.cursors.drain(1..);
+        self.jump(line, pos - self.rope.line_to_char(line));
+        match closers.len() {
+            0 => self.set_message(format!("Unmatched {}", bracket)),
+            count => self.set_message(format!("Unmatched {}, {} left open, balance! closes them", bracket, count)),
+        }
+    }
+
+    // Removes the escape sequences from the primary cursor's selection, or the whole buffer
+    // without one, telling how many there were
+    fn strip_escapes(&mut self) {
+        if !self.writable() {
+            return;
+        }
+        let selection = self.cursors.first()
+            .and_then(|cursor| cursor.selection(&self.rope))
+            .filter(|(start, end)| start < end);
+        let (start, end) = selection.unwrap_or((0, self.rope.len_chars()));
+        let (text, count) = strip_escapes(&self.rope.slice(start..end).to_string());
+        if count == 0 {
+            return self.set_message("No escape sequences");
+        }
+
+        let (line, col) = (self.line(), self.col());
+        let first = self.rope.char_to_line(start);
+        let removed = self.rope.char_to_line(end) - first + 1;
+        let lines = self.rope.len_lines();
+        self.rope.remove(start..end);
+        self.rope.insert(start, &text);
+        self.touch(first, removed, (removed + self.rope.len_lines()).saturating_sub(lines));
+        self.dirty = true;
+        self.cursors.drain(1..);
+        if selection.is_some() {
+            let line = self.rope.char_to_line(start);
+            self.set_cursor(line, start - self.rope.line_to_char(line));
+        } else {
+            self.set_cursor(line, col);
+        }
+        self.set_message(format!("{} escape sequences stripped", count));
+    }
+
+    fn hook(&mut self, filename: &str) {
+        let extension = Path::new(filename).extension().and_then(|extension| extension.to_str());
+        let command = match self.hooks.iter().find(|(ext, _)| Some(ext.as_str()) == extension) {
+            Some((_, command)) => command.clone(),
+            None => return,
+        };
+
+        // The file name is given to the command as its last argument
+        let output = process::Command::new("sh")
+            .arg("-c").arg(format!("{} \"$@\"", command)).arg("ted").arg(filename)
+            .stdin(process::Stdio::null())
+            .output();
+        match output {
+            Ok(output) => {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                let stderr = stderr.lines().find(|line| !line.trim().is_empty()).unwrap_or("");
+                if output.status.success() {
+                    if let Err(err) = self.reload() {
+                        self.set_message(format!("Cannot reload \"{}\": {}", filename, err));
+                    } else if !stderr.is_empty() {
+                        self.set_message(format!("{}: {}", command, stderr));
+                    }
+                } else if stderr.is_empty() {
+                    self.set_message(format!("{} failed ({})", command, output.status));
+                } else {
+                    self.set_message(format!("{} failed: {}", command, stderr));
+                }
+            }
+            Err(err) => self.set_message(format!("Cannot run {}: {}", command, err)),
+        }
+    }
+
+    // Removes the whitespace ending lines, makes their breaks the same and adds the one missing
+    // at the end of the buffer, as far as the options ask for it
+    fn terminate(&mut self) {
+        let mut touched = Vec::new();
+        terminate(&mut self.rope, &self.options, |line, removed, added| touched.push((line, removed, added)));
+        if touched.is_empty() {
+            return;
+        }
+        for (line, removed, added) in touched {
+            self.touch(line, removed, added);
+        }
+        self.dirty = true;
+        let rope = &self.rope;
+        for cursor in &mut self.cursors {
+            let col = min(cursor.col, columns(rope.line(cursor.line)));
+            cursor.apply(rope, Movement::GotoCol(col));
+            cursor.anchor = cursor.anchor.map(|(line, col)| (line, min(col, columns(rope.line(line)))));
+        }
+    }
+
+    // Applies the `.editorconfig` properties of the buffer's file to the options, returns
+    // whether they set the indentation
+    pub fn configure(&mut self) -> bool {
+        let properties = match &self.filename {
+            Some(filename) => editorconfig(filename),
+            None => return false,
+        };
+        let property = |key: &str| properties.iter().find(|(other, _)| other == key).map(|(_, value)| value.as_str());
+        match property("indent_style") {
+            Some("space") => self.options.soft_tabs = true,
+            Some("tab") => self.options.soft_tabs = false,
+            _ => {}
+        }
+        // An indent size of "tab" indents by the tab width, which is the indent size when not given
+        let size = match property("indent_size") {
+            Some("tab") => Some(0),
+            size => size.and_then(|size| size.parse::<usize>().ok()).filter(|&size| size > 0),
+        };
+        let tab_width = property("tab_width").and_then(|width| width.parse::<usize>().ok()).filter(|&width| width > 0);
+        if let Some(width) = tab_width.or(size.filter(|&size| size > 0)) {
+            self.options.tab_width = width;
+        }
+        if let Some(size) = size {
+            self.options.indent_width = size;
+        }
+        match property("trim_trailing_whitespace") {
+            Some("true") => self.options.trim_trailing = true,
+            Some("false") => self.options.trim_trailing = false,
+            _ => {}
+        }
+        match property("insert_final_newline") {
+            Some("true") => self.options.final_newline = true,
+            Some("false") => self.options.final_newline = false,
+            _ => {}
+        }
+        match property("end_of_line") {
+            Some("lf") => self.options.line_ending = LineEnding::Lf,
+            Some("crlf") => self.options.line_ending = LineEnding::CrLf,
+            Some("cr") => self.options.line_ending = LineEnding::Cr,
+            _ => {}
+        }
+        property("indent_style").is_some() || size.is_some() || tab_width.is_some()
+    }
+
+    // Size of the buffer as written to a file, for the status row. The bytes are those of the
+    // file, the encoding it is in may take more or fewer than the text.
+    fn written(&self, filename: &str) -> String {
+        let lines = self.rope.len_lines() - if columns(self.rope.line(lines(&self.rope))) == 0 { 1 } else { 0 };
+        let bytes = fs::metadata(filename).map_or(self.rope.len_bytes() as u64, |metadata| metadata.len());
+        format!("\"{}\" {}B, {} line{} written", filename, bytes, lines, if lines == 1 { "" } else { "s" })
+    }
+
+    pub fn save(&mut self) {
+        if !self.writable() {
+            return;
+        }
+        if let Some(filename) = self.filename.clone() {
+            self.terminate();
+            if self.rope.len_bytes() < BACKGROUND_SAVE {
+                let result = self.write(&filename);
+                self.saved(&filename, result);
+                return;
+            }
+            // A snapshot of the rope is written, it stays the same as the buffer is read-only meanwhile
+            let (sender, receiver) = channel();
+            let written = Arc::new(AtomicUsize::new(0));
+            let (rope, name, encoding, count) = (self.rope.clone(), filename.clone(), self.encoding, written.clone());
+            thread::spawn(move || sender.send(store(&rope, &name, encoding, &count)));
+            self.saving = Some(Saving { filename, written, result: receiver });
+        }
+    }
+
+    fn saved(&mut self, filename: &str, result: io::Result<()>) {
+        match result {
+            Ok(()) => {
+                self.dirty = false;
+                self.modified.clear();
+                self.rediff();
+                self.set_message(self.written(filename));
+                self.hook(filename);
+            }
+            Err(err) => self.set_message(format!("Cannot save \"{}\": {}", filename, err)),
+        }
+    }
+}
+
+pub enum Command {
+    Write { filename: Option<String>, quit: bool },
+    Quit { force: bool },
+    Revert { force: bool },
+    // The file opened again as saved, read-only in a buffer of its own, left to the caller
+    View,
+    // Encoding to save in, shown when not given
+    Encoding(Option<Encoding>),
+    Set(Vec<String>),
+    OnSave { extension: String, command: String },
+    // Command run only on buffers of files with the extension
+    For { extension: String, command: Box<Command> },
+    // 0-based line
+    Goto(usize),
+    Percent(usize),
+    // 0-based byte offset
+    Byte(usize),
+    Substitute { pattern: String, replacement: String, global: bool, all: bool },
+    Words { shrink: bool },
+    // Shell command the selection, or the whole buffer, is replaced with the output of
+    Filter(String),
+    // Character to line up instead of the cursors themselves
+    Align(Option<char>),
+    // Text width, the one of the options if not given
+    Reflow(Option<usize>),
+    // Indentation rewritten with tabs or spaces, as the options have it
+    Retab,
+    // Brackets checked, and those left open closed if asked to
+    Balance { close: bool },
+    // Sides of the lines whitespace is trimmed from
+    Trim { start: bool, end: bool },
+    // Escape sequences removed from the selection, or the whole buffer
+    StripEscapes,
+    // Delimiter the lines are joined with, a space if not given
+    Join(Option<String>),
+    // Format of the date to insert, the one of the options if not given
+    Date(Option<String>),
+}
+
+// Splits `s/old/new/flags` on its delimiter, the one following `s`, which can be escaped with '\\'
+fn split(text: &str) -> Vec<String> {
+    let mut chars = text.chars();
+    let delimiter = chars.next();
+    let mut parts = vec![String::new()];
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some(next) if Some(next) == delimiter => parts.last_mut().unwrap().push(next),
+                Some(next) => { parts.last_mut().unwrap().push(c); parts.last_mut().unwrap().push(next); }
+                None => parts.last_mut().unwrap().push(c),
+            }
+        } else if Some(c) == delimiter {
+            parts.push(String::new());
+        } else {
+            parts.last_mut().unwrap().push(c);
+        }
+    }
+    parts
+}
+
+impl Command {
+    // Whether the command only sets the buffer up, without editing it or acting on its file,
+    // which are the commands a configuration run on every buffer opened is limited to
+    pub fn configures(&self) -> bool {
+        match self {
+            Command::Set(_) | Command::OnSave { .. } => true,
+            Command::For { command, .. } => command.configures(),
+            _ => false,
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        let (name, arg) = match text.find(' ') {
+            Some(i) => (&text[..i], text[i + 1..].trim()),
+            None => (text, ""),
+        };
+
+        if let Some(command) = text.strip_prefix('!') {
+            return match command.trim() {
+                "" => Err("Usage: !<command>".to_string()),
+                command => Ok(Command::Filter(command.to_string())),
+            };
+        }
+
+        let filename = if arg.is_empty() { None } else { Some(arg.to_string()) };
+        match name {
+            "w" | "write" => return Ok(Command::Write { filename, quit: false }),
+            "wq" | "x" => return Ok(Command::Write { filename, quit: true }),
+            "q" | "quit" => return Ok(Command::Quit { force: false }),
+            "q!" | "quit!" => return Ok(Command::Quit { force: true }),
+            "encoding" => return match arg {
+                "" => Ok(Command::Encoding(None)),
+                name => match Encoding::from_name(name) {
+                    Some(encoding) => Ok(Command::Encoding(Some(encoding))),
+                    None => Err(format!("Unknown encoding, utf-8, latin1, windows-1252, utf-16le or utf-16be: {}", name)),
+                },
+            },
+            "retab" => return Ok(Command::Retab),
+            "balance" => return Ok(Command::Balance { close: false }),
+            "balance!" => return Ok(Command::Balance { close: true }),
+            "stripescapes" => return Ok(Command::StripEscapes),
+            // Quotes keep the spaces of a delimiter like ", "
+            "join" => return Ok(Command::Join(match arg {
+                "" => None,
+                _ => Some(arg.strip_prefix('"').and_then(|arg| arg.strip_suffix('"')).unwrap_or(arg).to_string()),
+            })),
+            "date" => return Ok(Command::Date(if arg.is_empty() { None } else { Some(arg.to_string()) })),
+            "view" => return Ok(Command::View),
+            "revert" => return Ok(Command::Revert { force: false }),
+            "revert!" => return Ok(Command::Revert { force: true }),
+            "set" => return Ok(Command::Set(arg.split_whitespace().map(String::from).collect())),
+            "align" => return match arg.chars().count() {
+                0 => Ok(Command::Align(None)),
+                1 => Ok(Command::Align(arg.chars().next())),
+                _ => Err(format!("Usage: align [character]: {}", text)),
+            },
+            "reflow" => return match arg {
+                "" => Ok(Command::Reflow(None)),
+                _ => match arg.parse::<usize>() {
+                    Ok(width) if width > 0 => Ok(Command::Reflow(Some(width))),
+                    _ => Err(format!("Invalid text width: {}", arg)),
+                },
+            },
+            "trim" => return match arg {
+                "" | "both" => Ok(Command::Trim { start: true, end: true }),
+                "start" => Ok(Command::Trim { start: true, end: false }),
+                "end" => Ok(Command::Trim { start: false, end: true }),
+                _ => Err(format!("Usage: trim [both|start|end]: {}", text)),
+            },
+            "words" => return match arg {
+                "" | "out" => Ok(Command::Words { shrink: false }),
+                "in" => Ok(Command::Words { shrink: true }),
+                _ => Err(format!("Usage: words [in|out]: {}", text)),
+            },
+            "onsave" => return match arg.find(' ') {
+                Some(i) => Ok(Command::OnSave {
+                    extension: arg[..i].trim_start_matches('.').to_string(),
+                    command: arg[i + 1..].trim().to_string(),
+                }),
+                None => Err(format!("Usage: onsave <extension> <command>: {}", text)),
+            },
+            "for" => return match arg.find(' ') {
+                Some(i) => Ok(Command::For {
+                    extension: arg[..i].trim_start_matches('.').to_string(),
+                    command: Box::new(Command::parse(&arg[i + 1..])?),
+                }),
+                None => Err(format!("Usage: for <extension> <command>: {}", text)),
+            },
+            "byte" => return match arg.parse::<usize>() {
+                Ok(offset) => Ok(Command::Byte(offset)),
+                Err(_) => Err(format!("Usage: byte <offset>: {}", text)),
+            },
+            _ => {}
+        }
+
+        let target = if name == "goto" { arg } else { text };
+        if let Some(percent) = target.strip_suffix('%') {
+            if let Ok(percent) = percent.trim().parse::<usize>() {
+                return Ok(Command::Percent(percent));
+            }
+        } else if let Ok(line) = target.parse::<usize>() {
+            return Ok(Command::Goto(line.saturating_sub(1)));
+        }
+
+        let (all, substitute) = match text.strip_prefix('%') { Some(rest) => (true, rest), None => (false, text) };
+        if let Some(rest) = substitute.strip_prefix('s') {
+            let parts = split(rest);
+            if rest.is_empty() || parts.len() < 2 || parts.len() > 3 || parts[0].is_empty() {
+                return Err(format!("Invalid substitution: {}", text));
+            }
+            let flags = parts.get(2).map(String::as_str).unwrap_or("");
+            if flags.chars().any(|c| c != 'g') {
+                return Err(format!("Invalid flags: {}", flags));
+            }
+            return Ok(Command::Substitute {
+                pattern: parts[0].clone(),
+                replacement: parts[1].clone(),
+                global: flags.contains('g'),
+                all,
+            });
+        }
+
+        Err(format!("Unknown command: {}", text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    fn movement() -> impl Strategy<Value = Movement> {
+        prop_oneof![
+            (0..4usize).prop_map(Movement::Up),
+            (0..4usize).prop_map(Movement::Down),
+            (0..4usize).prop_map(Movement::Left),
+            (0..4usize).prop_map(Movement::Right),
+            Just(Movement::Begin),
+            Just(Movement::End),
+            Just(Movement::LineBegin),
+            Just(Movement::LineEnd),
+            (0..12usize, 0..12usize).prop_map(|(line, col)| Movement::Goto(line, col)),
+            (0..12usize).prop_map(Movement::GotoLine),
+            (0..12usize).prop_map(Movement::GotoCol),
+        ]
+    }
+
+    fn key() -> impl Strategy<Value = Key> {
+        prop::sample::select(vec![
+            Key::Up, Key::Down, Key::Left, Key::Right, Key::Home, Key::End, Key::Ctrl('a'), Key::Ctrl('e'),
+            Key::Char('x'), Key::Char('\n'), Key::Char('\t'), Key::Char('{'), Key::Char('}'), Key::Backspace,
+            Key::Delete, Key::Alt('j'), Key::Alt('k'), Key::Esc, Key::Alt('.'), Key::Ctrl('k'), Key::Ctrl('y'),
+            Key::Alt('d'), Key::Alt('/'), Key::Alt('~'), Key::Alt('y'), Key::Shift(Nav::Down), Key::Shift(Nav::Right),
+            Key::Char('('), Key::Char(')'), Key::Char('"'),
+        ])
+    }
+
+    // Whether an .editorconfig section glob matches a path relative to its file
+    fn glob(glob: &str, path: &str) -> bool {
+        matches(&glob.chars().collect::<Vec<char>>(), &path.chars().collect::<Vec<char>>())
+    }
+
+    #[test]
+    fn glob_sets() {
+        assert!(glob("[abc].rs", "b.rs"));
+        assert!(!glob("[abc].rs", "d.rs"));
+        assert!(glob("[!abc].rs", "d.rs"));
+        assert!(!glob("[!abc].rs", "a.rs"));
+        assert!(glob("[a-c]x", "bx"));
+        assert!(!glob("[a-c]x", "dx"));
+        assert!(glob("[[]", "["));
+        assert!(glob("[[]]", "[]"));
+        assert!(glob("[foo[]]", "f]"));
+        assert!(glob("[]", "[]"));
+        assert!(glob("[!]", "[!]"));
+        assert!(glob("[ab", "[ab"));
+        assert!(!glob("[ab", "a"));
+        assert!(!glob("[", ""));
+    }
+
+    fn configured(name: &str) -> Editor {
+        let mut editor = Editor::new(Rope::new());
+        editor.filename = Some(format!("{}/tests/fixtures/editorconfig/{}", env!("CARGO_MANIFEST_DIR"), name));
+        editor.configure();
+        editor
+    }
+
+    #[test]
+    fn editorconfig_fixture() {
+        let editor = configured("main.c");
+        assert!(!editor.options.soft_tabs);
+        assert_eq!(editor.options.tab_width, 8);
+
+        let editor = configured("main.rs");
+        assert!(editor.options.soft_tabs);
+        assert_eq!((editor.options.tab_width, editor.options.indent_width), (8, 4));
+        assert!(!editor.options.final_newline);
+
+        // The file nearer to the buffer's takes precedence
+        let editor = configured("sub/main.rs");
+        assert_eq!(editor.options.indent_width, 2);
+        assert!(editor.options.final_newline);
+
+        let editor = configured("notes.txt");
+        assert!(editor.options.trim_trailing);
+
+        // Brackets that make no set are matched as they are
+        assert!(configured("[]").options.line_ending == LineEnding::CrLf);
+        assert!(configured("[ab").options.line_ending == LineEnding::Cr);
+        assert!(configured("a").options.line_ending != LineEnding::Cr);
+
+        // A section left unclosed is no section, its properties go to the one before it
+        assert_eq!(editorconfig(&format!("{}/tests/fixtures/editorconfig/main.py", env!("CARGO_MANIFEST_DIR"))), vec![
+            ("indent_style".to_string(), "tab".to_string()),
+            ("tab_width".to_string(), "8".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn count_matches_across_chunks() {
+        // Long enough for the rope to be split in chunks, at places an occurrence goes over
+        let mut split = false;
+        for lead in 0..3 {
+            let text = "-".repeat(lead) + &"abcé".repeat(3000);
+            let rope = Rope::from_str(&text);
+            let mut at = 0;
+            for chunk in rope.chunks() {
+                at += chunk.chars().count();
+                split |= at < rope.len_chars() && (at - lead) % 4 != 0;
+            }
+            assert!(rope.chunks().count() > 1);
+            for needle in ["abcé", "céa", "éab", "é"] {
+                assert_eq!(count_matches(&rope, needle, false), text.matches(needle).count(), "{}", needle);
+            }
+        }
+        assert!(split);
+    }
+
+    #[test]
+    fn count_matches_overlapping_and_cased() {
+        let count = |text: &str, needle: &str, case_insensitive: bool| count_matches(&Rope::from_str(text), needle, case_insensitive);
+        // Occurrences are not counted overlapping
+        assert_eq!(count("aaaa", "aa", false), 2);
+        assert_eq!(count("aaa", "aa", false), 1);
+        assert_eq!(count("abababa", "aba", false), 2);
+        assert_eq!(count("abc", "", false), 0);
+        assert_eq!(count("ab", "abc", false), 0);
+        // Characters are compared by their lower case, one for one
+        assert_eq!(count("äöÄÖäÖ", "ÄÖ", false), 1);
+        assert_eq!(count("äöÄÖäÖ", "ÄÖ", true), 3);
+        assert_eq!(count("École ÉCOLE école", "école", true), 3);
+        assert_eq!(count("ΣΑΣ σας", "σ", true), 3);
+        assert_eq!(count("İi", "i", true), 1);
+    }
+
+    #[test]
+    fn appearance() {
+        let path = scratch("appearance", b"ab\ncd\n");
+        let mut editor = Editor::open(path.clone());
+        // Messages are left out, each of the changes must tell without them
+        let appearance = |editor: &mut Editor| {
+            editor.message = None;
+            editor.appearance()
+        };
+        let before = appearance(&mut editor);
+        editor.key(Key::Null, 1);
+        assert_eq!(appearance(&mut editor), before);
+
+        editor.touch(0, 1, 1);
+        let touched = appearance(&mut editor);
+        assert_ne!(touched, before);
+        run(&mut editor, "set tabwidth=3");
+        let set = appearance(&mut editor);
+        assert_ne!(set, touched);
+        editor.reload().unwrap();
+        let reloaded = appearance(&mut editor);
+        assert_ne!(reloaded, set);
+        run(&mut editor, "revert");
+        assert_ne!(appearance(&mut editor), reloaded);
+        fs::remove_file(&path).unwrap();
+    }
+
+    // Text of a buffer after typing keys at its end with the options set
+    fn typed(text: &str, options: &str, keys: &[Key]) -> Editor {
+        let mut editor = buffer(text, "rs");
+        run(&mut editor, options);
+        editor.key(Key::End, 1);
+        for &key in keys {
+            editor.key(key, 1);
+        }
+        editor
+    }
+
+    #[test]
+    fn smart_indent() {
+        use Key::Char;
+        let editor = typed("fn f() ", "set smartindent", &[Char('{'), Char('\n'), Char('}')]);
+        assert_eq!(editor.rope.to_string(), "fn f() {\n}");
+        let keys = [Char('{'), Char('\n'), Char('{'), Char('\n'), Char('x'), Char('\n'), Char('}'), Char('\n'), Char('}')];
+        let editor = typed("fn f() ", "set smartindent", &keys);
+        assert_eq!(editor.rope.to_string(), "fn f() {\n\t{\n\t\tx\n\t}\n}");
+        let editor = typed("fn f() ", "set smartindent softtabs", &keys);
+        assert_eq!(editor.rope.to_string(), "fn f() {\n    {\n        x\n    }\n}");
+        // Without it the indentation is that of the line before
+        let editor = typed("  f ", "set nosmartindent", &keys);
+        assert_eq!(editor.rope.to_string(), "  f {\n  {\n  x\n  }\n  }");
+    }
+
+    #[test]
+    fn auto_pairs() {
+        use Key::{Backspace, Char, F};
+        let text = |editor: &Editor| (editor.rope.to_string(), editor.col());
+        let editor = typed("f", "set autopairs", &[Char('('), Char('"'), Char('x'), Char('"'), Char(')'), Char(';')]);
+        assert_eq!(text(&editor), ("f(\"x\");".to_string(), 7));
+        // Not before a word, nor a quote after one
+        let editor = typed("", "set autopairs", &[Char('['), Char('a'), Char('"'), Char('b'), Char('\n')]);
+        assert_eq!(text(&editor), ("[a\"b\n]".to_string(), 0));
+        let mut editor = buffer("x\n", "");
+        run(&mut editor, "set autopairs");
+        editor.key(Char('{'), 1);
+        assert_eq!(editor.rope.to_string(), "{x\n");
+        // Backspace between a pair takes both away, only when nothing is left between them
+        let editor = typed("", "set autopairs", &[Char('('), Char('('), Backspace]);
+        assert_eq!(text(&editor), ("()".to_string(), 1));
+        let editor = typed("", "set autopairs", &[Char('('), Char('a'), Backspace, Backspace]);
+        assert_eq!(text(&editor), ("".to_string(), 0));
+        // Moving over a closing bracket leaves the buffer unchanged
+        let mut editor = buffer("()", "");
+        run(&mut editor, "set autopairs");
+        editor.set_cursor(0, 1);
+        editor.key(Char(')'), 1);
+        assert_eq!((text(&editor), editor.dirty), (("()".to_string(), 2), false));
+
+        // Neither in paste mode, nor without the option
+        for (options, keys) in [("set autopairs", &[F(2), Char('('), Char(')'), Backspace][..]), ("set noautopairs", &[Char('('), Char(')'), Backspace][..])] {
+            let editor = typed("", options, keys);
+            assert_eq!(text(&editor), ("(".to_string(), 1), "{}", options);
+        }
+    }
+
+    #[test]
+    fn backspace_after_auto_indent() {
+        use Key::{Backspace, Char};
+        // The whole indentation given goes with one Backspace, nested or not
+        for (text, options) in [("    {", "set smartindent softtabs"), ("\t{", "set smartindent"), ("    a", "set softtabs")] {
+            let editor = typed(text, options, &[Char('\n'), Backspace]);
+            assert_eq!(editor.rope.to_string(), text.to_string() + "\n", "{}", text);
+            assert_eq!((editor.line(), editor.col()), (1, 0));
+        }
+        // Not after typing on the line, nor without the option, which takes away a level
+        let editor = typed("    a", "set softtabs", &[Char('\n'), Char('b'), Backspace]);
+        assert_eq!(editor.rope.to_string(), "    a\n    ");
+        let editor = typed("    {", "set smartindent softtabs nobackspaceindent", &[Char('\n'), Backspace]);
+        assert_eq!(editor.rope.to_string(), "    {\n    ");
+        let editor = typed("    {", "set smartindent nobackspaceindent", &[Char('\n'), Backspace]);
+        assert_eq!(editor.rope.to_string(), "    {\n    ");
+    }
+
+    // File of its own for a test in the temporary directory, with the bytes given
+    fn scratch(name: &str, bytes: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("ted-{}-{}", name, process::id()));
+        fs::write(&path, bytes).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn files_without_a_final_line_break() {
+        let path = scratch("unterminated", b"hello");
+        let mut editor = Editor::open(path.clone());
+        editor.options.final_newline = false;
+        editor.key(Key::End, 1);
+        assert_eq!((editor.line(), editor.col()), (0, 5));
+        editor.key(Key::Char('!'), 1);
+        editor.save();
+        assert_eq!(fs::read(&path).unwrap(), b"hello!");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn latin1_round_trip() {
+        let bytes = (0x80..=0xff).chain(b"\nend\n".iter().copied()).collect::<Vec<u8>>();
+        let path = scratch("latin1", &bytes);
+        let mut editor = Editor::open_as(path.clone(), Some(Encoding::Latin1));
+        assert_eq!(editor.rope.char(0), '\u{80}');
+        assert_eq!(editor.rope.char(0x7f), '\u{ff}');
+        editor.dirty = true;
+        editor.save();
+        assert_eq!(fs::read(&path).unwrap(), bytes);
+
+        // Text not in Latin-1 is not written
+        editor.key(Key::Char('\u{20ac}'), 1);
+        editor.save();
+        assert!(editor.dirty);
+        assert_eq!(fs::read(&path).unwrap(), bytes);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn windows1252_round_trip() {
+        // 0x81 is not defined in Windows-1252 but still comes back as it was
+        let bytes = vec![0x80, 0x81, 0x9f, b'a', 0xe9];
+        let (text, replaced) = Encoding::Windows1252.decode(&bytes);
+        assert_eq!(text, "\u{20ac}\u{81}\u{178}a\u{e9}");
+        assert!(!replaced);
+        assert_eq!(Encoding::Windows1252.encode(&text), Ok(bytes));
+        assert_eq!(Encoding::Windows1252.encode("a\u{3b1}"), Err('\u{3b1}'));
+    }
+
+    #[test]
+    fn utf16() {
+        assert_eq!(Encoding::Utf16Le.decode(&[0xff, 0xfe, b'a', 0, 0x3d, 0xd8, 0x00, 0xde]), ("a\u{1f600}".to_string(), false));
+        assert_eq!(Encoding::Utf16Be.encode("a\u{1f600}"), Ok(vec![0, b'a', 0xd8, 0x3d, 0xde, 0x00]));
+
+        // A lone surrogate
+        assert_eq!(Encoding::Utf16Le.decode(&[0x3d, 0xd8, b'a', 0]), ("\u{fffd}a".to_string(), true));
+        assert_eq!(Encoding::Utf16Be.decode(&[0xde, 0x00]), ("\u{fffd}".to_string(), true));
+
+        // An odd trailing byte
+        let (text, replaced) = Encoding::Utf16Le.decode(&[b'a', 0, b'b']);
+        assert!(text.starts_with('a'));
+        assert!(replaced);
+    }
+
+    #[test]
+    fn modified_lines() {
+        let path = scratch("modified", b"one\ntwo\nthree\nfour\nfive\n");
+        let mut editor = Editor::open(path.clone());
+        assert!(editor.modified.is_empty());
+        editor.set_cursor(1, 3);
+        editor.key(Key::Char('x'), 1);
+        editor.set_cursor(3, 0);
+        editor.key(Key::Char('\n'), 1);
+        let marked = |editor: &Editor| (0..editor.rope.len_lines()).filter(|&line| editor.modified.get(line) == Some(&true)).collect::<Vec<_>>();
+        assert_eq!(marked(&editor), vec![1, 3, 4]);
+
+        // Alt-m and Alt-M go to the start of each run of them, around the ends of the buffer
+        editor.set_cursor(0, 0);
+        let mut starts = Vec::new();
+        for _ in 0..3 {
+            editor.key(Key::Alt('m'), 1);
+            starts.push(editor.line());
+        }
+        assert_eq!(starts, vec![1, 3, 1]);
+        editor.key(Key::Alt('M'), 1);
+        assert_eq!(editor.line(), 3);
+
+        editor.save();
+        assert!(!editor.dirty);
+        assert!(marked(&editor).is_empty());
+        editor.key(Key::Alt('m'), 1);
+        assert_eq!((editor.line(), message(&editor)), (3, Some("No changes")));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn written_to_another_file() {
+        let path = scratch("written", b"one  \ntwo");
+        let other = format!("{}-other", path);
+        let mut editor = Editor::open(path.clone());
+        run(&mut editor, "set trimtrailing finalnewline");
+        editor.set_cursor(0, 5);
+        run(&mut editor, &format!("w {}", other));
+        assert_eq!(fs::read_to_string(&other).unwrap(), "one\ntwo\n");
+        // The buffer and its own file stay as they were
+        assert_eq!(editor.rope.to_string(), "one  \ntwo");
+        assert_eq!((editor.dirty, editor.modified.contains(&true), positions(&editor)), (false, false, vec![(0, 5)]));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one  \ntwo");
+
+        run(&mut editor, "w");
+        assert_eq!((editor.rope.to_string(), positions(&editor)), ("one\ntwo\n".to_string(), vec![(0, 3)]));
+        assert!(!editor.dirty);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&other).unwrap();
+    }
+
+    #[test]
+    fn revert() {
+        let path = scratch("revert", b"one\ntwo\nthree\n");
+        let mut editor = Editor::open(path.clone());
+        editor.set_cursor(2, 3);
+        editor.key(Key::Ctrl('k'), 1);
+        editor.key(Key::Char('x'), 1);
+        assert!(editor.dirty);
+        run(&mut editor, "revert");
+        assert!(editor.dirty);
+        assert_eq!(message(&editor), Some("No write since last change (add ! to override)"));
+        run(&mut editor, "revert!");
+        assert_eq!(editor.rope.to_string(), fs::read_to_string(&path).unwrap());
+        assert!(!editor.dirty);
+        assert!(editor.modified.is_empty());
+        assert_eq!((editor.line(), editor.col()), (2, 1));
+
+        // Without a file the buffer is emptied
+        let mut editor = buffer("text", "");
+        editor.key(Key::Char('x'), 1);
+        run(&mut editor, "revert!");
+        assert_eq!(editor.rope.to_string(), "");
+        assert!(!editor.dirty);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn goto_byte() {
+        // Bytes 3 to 9 are 'a', 'é' in two bytes, '€' in three and 'b'
+        let mut editor = buffer("\u{e9}\na\u{e9}\u{20ac}b\n", "");
+        for (offset, position) in [(0, (0, 0)), (2, (0, 1)), (3, (1, 0)), (4, (1, 1)), (6, (1, 2)), (9, (1, 3)), (10, (1, 4))] {
+            editor.goto_byte(offset);
+            assert_eq!((editor.line(), editor.col()), position, "{}", offset);
+        }
+        // Within a character is at it, past the end at the end
+        editor.goto_byte(7);
+        assert_eq!((editor.line(), editor.col()), (1, 2));
+        editor.goto_byte(1000);
+        assert_eq!((editor.line(), editor.col()), (2, 0));
+        run(&mut editor, "byte 5");
+        assert_eq!((editor.line(), editor.col()), (1, 1));
+    }
+
+    #[test]
+    fn word_chars() {
+        for (setting, word, next) in [
+            ("programming", "foo_bar", (0, 12)),
+            ("prose", "foo", (0, 8)),
+            ("-", "foo", (0, 8)),
+            ("_-", "foo_bar", (0, 12)),
+        ] {
+            let mut editor = buffer("foo_bar foo foo_bar\n", "");
+            run(&mut editor, &format!("set wordchars={}", setting));
+            editor.key(Key::Alt('*'), 1);
+            assert_eq!(editor.search.as_deref(), Some(word), "{}", setting);
+            assert_eq!((editor.line(), editor.col()), next, "{}", setting);
+
+            // Selections are widened to the words they cut through
+            editor.set_cursor(0, 2);
+            editor.key(Key::Shift(Nav::Right), 1);
+            run(&mut editor, "words");
+            assert_eq!(editor.cursors[0].selection(&editor.rope), Some((0, word.len())), "{}", setting);
+        }
+    }
+
+    #[test]
+    fn tabs_kept_or_expanded() {
+        // Tabs kept are a character each, drawn up to the next tab stop, and Backspace takes one away
+        let mut editor = typed("a", "set nosofttabs tabwidth=4", &[Key::Char('\t'), Key::Char('b')]);
+        assert_eq!(editor.rope.to_string(), "a\tb");
+        assert_eq!(editor.col(), 3);
+        let line = editor.rope.line(0);
+        assert_eq!((cells(line.slice(..1), 0, 4), cells(line.slice(..2), 0, 4)), (1, 4));
+        editor.key(Key::Left, 1);
+        editor.key(Key::Left, 1);
+        assert_eq!(editor.col(), 1);
+        editor.key(Key::Right, 1);
+        editor.key(Key::Backspace, 1);
+        assert_eq!(editor.rope.to_string(), "ab");
+
+        // Expanded, they are spaces up to the tab stop, which Backspace takes away together in the indentation
+        let editor = typed("a", "set softtabs tabwidth=4", &[Key::Char('\t')]);
+        assert_eq!(editor.rope.to_string(), "a   ");
+        assert_eq!(editor.col(), 4);
+        let mut editor = typed("", "set softtabs tabwidth=4", &[Key::Char('\t'), Key::Char('\t')]);
+        assert_eq!(editor.rope.to_string(), "        ");
+        editor.key(Key::Backspace, 1);
+        assert_eq!((editor.rope.to_string(), editor.col()), ("    ".to_string(), 4));
+    }
+
+    fn text(rope: &Rope) -> Vec<String> {
+        (0..rope.len_lines()).map(|i| rope.line(i).slice(..columns(rope.line(i))).to_string()).collect()
+    }
+
+    // Length of the longest common subsequence of the lines, the slow way
+    fn oracle(a: &[String], b: &[String]) -> usize {
+        let mut table = vec![vec![0; b.len() + 1]; a.len() + 1];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                table[i][j] = if a[i] == b[j] { table[i + 1][j + 1] + 1 } else { max(table[i + 1][j], table[i][j + 1]) };
+            }
+        }
+        table[0][0]
+    }
+
+    // The rows show every line of both texts once and in order, pairing as many equal lines as can be
+    fn check_compare(a: &str, b: &str) -> Result<(), TestCaseError> {
+        let (a, b) = (Rope::from_str(a), Rope::from_str(b));
+        let rows = compare(&a, &b);
+        let (lines_a, lines_b) = (text(&a), text(&b));
+        let (mut shown_a, mut shown_b, mut same) = (Vec::new(), Vec::new(), 0);
+        for row in rows {
+            match row {
+                Row::Same(i, j) => {
+                    prop_assert_eq!(&lines_a[i], &lines_b[j]);
+                    same += 1;
+                    shown_a.push(i);
+                    shown_b.push(j);
+                }
+                Row::Changed(i, j) => {
+                    shown_a.push(i);
+                    shown_b.push(j);
+                }
+                Row::Removed(i) => shown_a.push(i),
+                Row::Added(j) => shown_b.push(j),
+            }
+        }
+        prop_assert_eq!(shown_a, (0..lines_a.len()).collect::<Vec<usize>>());
+        prop_assert_eq!(shown_b, (0..lines_b.len()).collect::<Vec<usize>>());
+        prop_assert_eq!(same, oracle(&lines_a, &lines_b));
+        Ok(())
+    }
+
+    #[test]
+    fn compare_edges() {
+        check_compare("", "").unwrap();
+        check_compare("", "a\nb\n").unwrap();
+        check_compare("a\nb\n", "").unwrap();
+        check_compare("a\nb\nc", "a\nb\nc").unwrap();
+        assert_eq!(compare(&Rope::from_str("a\nb"), &Rope::from_str("a\nb")), vec![Row::Same(0, 0), Row::Same(1, 1)]);
+        assert_eq!(compare(&Rope::from_str("a\nb"), &Rope::from_str("a\nc")), vec![Row::Same(0, 0), Row::Changed(1, 1)]);
+    }
+
+    #[test]
+    fn compared_buffers_are_not_reloaded() {
+        let path = scratch("compared", b"a\nb\n");
+        let mut editor = Editor::new(Rope::from_str("a\n"));
+        editor.filename = Some(path.clone());
+        editor.compared(&compare(&Rope::from_str("a\n"), &Rope::from_str("b\n")), true);
+        assert!(editor.reload().is_err());
+        editor.command(&Command::Revert { force: true });
+        assert_eq!(editor.rope.to_string(), "a\n");
+        fs::remove_file(&path).unwrap();
+    }
+
+    fn buffer(text: &str, language: &str) -> Editor {
+        let mut editor = Editor::new(Rope::from_str(text));
+        editor.language = Some(language.to_string());
+        editor
+    }
+
+    fn message(editor: &Editor) -> Option<&str> {
+        editor.message.as_ref().map(|(message, _)| message.as_str())
+    }
+
+    fn select_all(editor: &mut Editor) {
+        editor.key(Key::End, 1);
+        editor.key(Key::Shift(Nav::Home), 1);
+    }
+
+    #[test]
+    fn comments() {
+        let mut editor = buffer("fn main() {\n    body();\n\n}\n", "rs");
+        select_all(&mut editor);
+        editor.key(Key::Alt('/'), 1);
+        assert_eq!(editor.rope.to_string(), "// fn main() {\n//     body();\n\n// }\n");
+
+        let mut editor = buffer("  a\n    b", "css");
+        select_all(&mut editor);
+        editor.key(Key::Alt('/'), 1);
+        assert_eq!(editor.rope.to_string(), "  /* a */\n  /*   b */");
+
+        // Comments with nothing in them are taken away whole
+        for text in ["/* */", "/**/", "/*  */"] {
+            let mut editor = buffer(text, "css");
+            editor.key(Key::Alt('/'), 1);
+            assert_eq!(editor.rope.to_string(), "");
+        }
+    }
+
+    // Every key of the arms of `dispatch` that changes the text is one of the edits
+    #[test]
+    fn edits_are_all_guarded() {
+        let keys = [
+            Key::Up, Key::Down, Key::Left, Key::Right, Key::Home, Key::End, Key::PageUp, Key::PageDown, Key::Ctrl('u'),
+            Key::Ctrl('d'), Key::Ctrl('a'), Key::Ctrl('e'), Key::Char('\n'), Key::Char('\t'), Key::Char('}'), Key::Char('a'),
+            Key::Backspace, Key::Delete, Key::Ctrl('o'), Key::Alt('o'), Key::Alt('u'), Key::Alt('d'), Key::Alt('~'),
+            Key::Alt('/'), Key::Ctrl('7'), Key::Alt('b'), Key::Alt('B'), Key::Alt('x'), Key::Alt('*'), Key::Alt('#'),
+            Key::Alt('m'), Key::Alt('M'), Key::Alt('e'), Key::Alt('E'), Key::Alt('z'), Key::Alt('Z'), Key::Alt('j'),
+            Key::Alt('k'), Key::Ctrl('k'), Key::Ctrl('y'), Key::Alt('y'), Key::Esc, Key::Alt('t'), Key::F(2), Key::Insert,
+            Key::Alt('q'), Key::Alt('.'), Key::Alt('@'), Key::Null,
+        ];
+        for key in keys {
+            for selected in [false, true] {
+                let mut editor = buffer("(ab)\n  cd\n", "rs");
+                editor.register = vec!["x".to_string()];
+                editor.key(Key::Right, 1);
+                if selected {
+                    editor.key(Key::Shift(Nav::Right), 1);
+                }
+                editor.key(key, 1);
+                assert!(editor.rope == "(ab)\n  cd\n" || Editor::edits(key), "{:?} edits", key);
+            }
+        }
+    }
+
+    #[test]
+    fn swap_register() {
+        let mut editor = buffer("abc\n", "");
+        editor.key(Key::Alt('y'), 1);
+        assert_eq!(editor.rope.to_string(), "abc\n");
+        assert_eq!(message(&editor), Some("Register is empty"));
+
+        editor.register = vec!["x".to_string()];
+        editor.key(Key::Right, 1);
+        editor.key(Key::Alt('y'), 1);
+        assert_eq!(editor.rope.to_string(), "axc\n");
+        assert_eq!(editor.register, vec!["b"]);
+
+        // What was swapped out is pasted at the cursor, not as a line
+        editor.key(Key::End, 1);
+        editor.key(Key::Ctrl('y'), 1);
+        assert_eq!(editor.rope.to_string(), "axc\nb");
+        assert_eq!((editor.line(), editor.col()), (1, 1));
+
+        // Lines are still pasted above the cursor's
+        editor.register = vec!["line\n".to_string()];
+        editor.key(Key::Ctrl('y'), 1);
+        assert_eq!(editor.rope.to_string(), "axc\nline\nb");
+        assert_eq!((editor.line(), editor.col()), (2, 0));
+
+        // Alt-. swaps again, and the swap ends the run of typing before it
+        let mut editor = buffer("abc\n", "");
+        editor.register = vec!["x".to_string()];
+        editor.key(Key::Char('1'), 1);
+        editor.key(Key::Alt('y'), 1);
+        assert_eq!((editor.rope.to_string(), editor.register.clone()), ("1xbc\n".to_string(), vec!["a".to_string()]));
+        assert!(editor.dirty);
+        editor.key(Key::Alt('.'), 1);
+        assert_eq!((editor.rope.to_string(), editor.register.clone()), ("1abc\n".to_string(), vec!["x".to_string()]));
+        editor.key(Key::Char('2'), 1);
+        editor.key(Key::Alt('.'), 1);
+        assert_eq!(editor.rope.to_string(), "122abc\n");
+    }
+
+    #[test]
+    fn balance() {
+        let rs = Some(super::comments("rs"));
+        assert_eq!(unbalanced("fn f(a: [u8; 2]) { g(a) }", rs), None);
+        // A closing bracket not matching the last one open
+        assert_eq!(unbalanced("f(a[0)]", rs), Some((5, Vec::new())));
+        assert_eq!(unbalanced("a)", rs), Some((1, Vec::new())));
+        // Brackets left open, closed innermost first
+        assert_eq!(unbalanced("f(a, [b, {c", rs), Some((1, vec!['}', ']', ')'])));
+        // Brackets in comments, strings and characters
+        assert_eq!(unbalanced("f() // (\n/* [ */ g(\"{\", '(', '\\'', '\\\\')", rs), None);
+        assert_eq!(unbalanced("f(\"(\"", rs), Some((1, vec![')'])));
+        assert_eq!(unbalanced("a '(' b", None), Some((3, vec![')'])));
+        // A quote not closed on its line, or a lifetime, is no string or character
+        assert_eq!(unbalanced("\"(\n)", rs), None);
+        assert_eq!(unbalanced("fn f<'a>(x: &'a [u8]) {}", rs), None);
+    }
+
+    fn run(editor: &mut Editor, command: &str) {
+        editor.command(&Command::parse(command).unwrap());
+    }
+
+    fn positions(editor: &Editor) -> Vec<(usize, usize)> {
+        editor.cursors.iter().map(|cursor| (cursor.line(), cursor.col(&editor.rope))).collect()
+    }
+
+    #[test]
+    fn last_line_after_a_line_break() {
+        // Down from the last line of text goes to the empty line after its break, keeping the column to come back with
+        let mut editor = buffer("ab\ncde\n", "");
+        editor.set_cursor(1, 3);
+        editor.key(Key::Down, 1);
+        assert_eq!(positions(&editor), vec![(2, 0)]);
+        editor.key(Key::Down, 1);
+        assert_eq!(positions(&editor), vec![(2, 0)]);
+        editor.key(Key::Up, 1);
+        assert_eq!(positions(&editor), vec![(1, 3)]);
+        editor.key(Key::Down, 1);
+        editor.key(Key::Char('x'), 1);
+        assert_eq!((editor.rope.to_string(), positions(&editor)), ("ab\ncde\nx".to_string(), vec![(2, 1)]));
+
+        // End is the same position, past a line break of any kind
+        for text in &["ab\ncde\n", "ab\r\ncde\r\n"] {
+            let mut editor = buffer(text, "");
+            editor.key(Key::End, 1);
+            assert_eq!(positions(&editor), vec![(2, 0)]);
+            assert_eq!(editor.cursors[0].pos(&editor.rope), end(&editor.rope));
+            editor.key(Key::Char('x'), 1);
+            assert_eq!(editor.rope.to_string(), format!("{}x", text));
+        }
+
+        // Without a final break, the last line is the one with text
+        let mut editor = buffer("ab\ncde", "");
+        editor.key(Key::End, 1);
+        assert_eq!(positions(&editor), vec![(1, 3)]);
+        editor.key(Key::Down, 1);
+        assert_eq!(positions(&editor), vec![(1, 3)]);
+    }
+
+    #[test]
+    fn shifted_movements_select() {
+        let mut editor = buffer("abcdef\nghijkl\n", "");
+        editor.set_cursor(0, 1);
+        editor.key(Key::Shift(Nav::Right), 1);
+        editor.key(Key::Shift(Nav::Right), 1);
+        assert_eq!(editor.selection(), Some(((0, 1), (0, 3))));
+        editor.key(Key::Shift(Nav::Down), 1);
+        assert_eq!(editor.selection(), Some(((0, 1), (1, 3))));
+        editor.key(Key::Shift(Nav::Left), 1);
+        assert_eq!(editor.selection(), Some(((0, 1), (1, 2))));
+
+        // Moving without Shift drops the anchor, so the next Shift starts from the cursor
+        for &key in &[Key::Right, Key::Left, Key::Up, Key::Down, Key::PageDown, Key::Ctrl('a')] {
+            let mut editor = buffer("abcdef\nghijkl\n", "");
+            editor.set_cursor(0, 1);
+            editor.key(Key::Shift(Nav::Right), 1);
+            editor.key(key, 1);
+            assert_eq!(editor.selection(), None, "{:?}", key);
+            let at = editor.primary_cursor();
+            editor.key(Key::Shift(Nav::Right), 1);
+            assert_eq!(editor.selection().map(|(start, _)| start), Some(at), "{:?}", key);
+        }
+    }
+
+    #[test]
+    fn other_end_of_the_selection() {
+        let mut editor = buffer("abcdef\nghijkl\n", "");
+        editor.set_cursor(0, 2);
+        editor.key(Key::Shift(Nav::Down), 1);
+        editor.key(Key::Shift(Nav::Right), 1);
+        let selection = editor.selection();
+        editor.key(Key::Alt('x'), 1);
+        assert_eq!((editor.primary_cursor(), editor.selection()), ((0, 2), selection));
+        editor.key(Key::Alt('x'), 1);
+        assert_eq!((positions(&editor), editor.cursors[0].anchor, editor.selection()), (vec![(1, 3)], Some((0, 2)), selection));
+
+        // Movements then change the end the cursor went to
+        editor.key(Key::Alt('x'), 1);
+        editor.key(Key::Shift(Nav::Left), 1);
+        assert_eq!(editor.selection(), Some(((0, 1), (1, 3))));
+
+        // Without a selection, nothing moves
+        editor.set_cursor(1, 1);
+        editor.key(Key::Alt('x'), 1);
+        assert_eq!((positions(&editor), editor.selection()), (vec![(1, 1)], None));
+    }
+
+    #[test]
+    fn detected_indentation() {
+        let detected = |text: &str| {
+            let mut editor = buffer(text, "");
+            editor.options.indent_width = 3;
+            editor.detect_indentation();
+            (editor.options.soft_tabs, editor.options.indent_width, message(&editor).map(str::to_string))
+        };
+        assert_eq!(detected("fn a() {\n\tb();\n\tif c {\n\t\td();\n\t}\n}\n"), (false, 0, Some("Indentation: tabs".to_string())));
+        assert_eq!(detected("a:\n  b:\n    c: 1\n  d: 2\n"), (true, 2, Some("Indentation: 2 spaces".to_string())));
+        assert_eq!(detected("def a():\n    if b:\n        c()\n    d()\n"), (true, 4, Some("Indentation: 4 spaces".to_string())));
+        // Lines continued further in than a level don't change it
+        assert_eq!(detected("a = [\n    1,\n    2,\n]\nif b:\n    call(1,\n           2)\n    c()\n"), (true, 4, Some("Indentation: 4 spaces".to_string())));
+
+        // Nothing to go by, or as much of both, keeps the options
+        for text in &["", "a\nb\n", "\n\n  \n", "a\n\tb\n    c\n"] {
+            assert_eq!(detected(text), (false, 3, None), "{:?}", text);
+        }
+
+        let mut editor = buffer("a\n\tb\n", "");
+        run(&mut editor, "set softtabs nodetectindent");
+        editor.detect_indentation();
+        assert!(editor.options.soft_tabs);
+    }
+
+    #[test]
+    fn pasted_at_each_cursor() {
+        // As many pieces as cursors go one to each, from the top of the buffer down
+        let mut editor = buffer("a\nb\nc\n", "");
+        editor.register = vec!["1\n".to_string(), "2\n".to_string(), "3\n".to_string()];
+        editor.set_cursors(&[(2, 0), (0, 0), (1, 0)]);
+        editor.key(Key::Ctrl('y'), 1);
+        assert_eq!(editor.rope.to_string(), "1\na\n2\nb\n3\nc\n");
+
+        // Otherwise each cursor gets all of them
+        let mut editor = buffer("a\nb\nc\n", "");
+        editor.register = vec!["1\n".to_string(), "2\n".to_string(), "3\n".to_string()];
+        editor.set_cursors(&[(0, 0), (2, 0)]);
+        editor.key(Key::Ctrl('y'), 1);
+        assert_eq!(editor.rope.to_string(), "1\n2\n3\na\nb\n1\n2\n3\nc\n");
+
+        // Lines cut with several cursors and pasted with as many
+        let mut editor = buffer("a\nb\nc\nd\n", "");
+        editor.set_cursors(&[(0, 0), (2, 0)]);
+        editor.key(Key::Ctrl('k'), 1);
+        assert_eq!((editor.rope.to_string(), editor.register.len()), ("b\nd\n".to_string(), 2));
+        editor.set_cursors(&[(1, 0), (2, 0)]);
+        editor.key(Key::Ctrl('y'), 1);
+        assert_eq!(editor.rope.to_string(), "b\na\nd\nc\n");
+        editor.set_cursors(&[(0, 0)]);
+        editor.key(Key::Ctrl('y'), 1);
+        assert_eq!(editor.rope.to_string(), "a\nc\nb\na\nd\nc\n");
+    }
+
+    #[test]
+    fn selections_across_lines_removed() {
+        // The same text goes whichever end the cursor is at, leaving the cursor where the selection started
+        for &(from, to) in &[((1, 2), (4, 3)), ((4, 3), (1, 2))] {
+            for &key in &[Key::Backspace, Key::Delete] {
+                let mut editor = buffer("zero\none\ntwo\nthree\nfour\nfive\n", "");
+                editor.set_cursor(from.0, from.1);
+                editor.cursors[0].anchor = Some(from);
+                editor.cursors[0].apply(&editor.rope, Movement::Goto(to.0, to.1));
+                assert_eq!(editor.selection(), Some(((1, 2), (4, 3))));
+                editor.key(key, 1);
+                assert_eq!(editor.rope.to_string(), "zero\nonr\nfive\n", "{:?} {:?}", from, key);
+                assert_eq!((positions(&editor), editor.selection()), (vec![(1, 2)], None));
+            }
+        }
+    }
+
+    #[test]
+    fn macros() {
+        let mut editor = buffer("a\nb\nc\nd\n", "");
+        let pressed = |editor: &mut Editor, keys: &[Key]| for &key in keys {
+            editor.record(key);
+            editor.key(key, 1);
+        };
+        pressed(&mut editor, &[Key::Alt('q'), Key::Char('-'), Key::Down, Key::Ctrl('a'), Key::Alt('q')]);
+        assert_eq!(editor.recorded, vec![Key::Char('-'), Key::Down, Key::Ctrl('a')]);
+        assert!(editor.recording.is_none());
+
+        // Played back as many times as the count, while recording another one, which gets neither
+        pressed(&mut editor, &[Key::Alt('q'), Key::Alt('2'), Key::Alt('@'), Key::Char('+'), Key::Alt('q')]);
+        assert_eq!(editor.rope.to_string(), "-a\n-b\n-c\n+d\n");
+        assert_eq!(editor.recorded, vec![Key::Char('+')]);
+    }
+
+    #[test]
+    fn jumps_flash() {
+        let flashed = |editor: &mut Editor| editor.flash.take().map(|(line, _)| line);
+        let mut editor = buffer("word\nother\n(\nword\n", "rs");
+        editor.key(Key::Down, 1);
+        assert_eq!(flashed(&mut editor), None);
+        run(&mut editor, "1");
+        assert_eq!(flashed(&mut editor), Some(0));
+        editor.key(Key::Alt('*'), 1);
+        assert_eq!(flashed(&mut editor), Some(3));
+        editor.key(Key::Alt('#'), 1);
+        assert_eq!(flashed(&mut editor), Some(0));
+        run(&mut editor, "byte 6");
+        assert_eq!(flashed(&mut editor), Some(1));
+        run(&mut editor, "balance");
+        assert_eq!(flashed(&mut editor), Some(2));
+
+        editor.key(Key::Alt('m'), 1);
+        assert_eq!(flashed(&mut editor), None);
+        editor.set_cursor(1, 0);
+        editor.key(Key::Char('x'), 1);
+        editor.set_cursor(3, 0);
+        editor.key(Key::Alt('m'), 1);
+        assert_eq!(flashed(&mut editor), Some(1));
+    }
+
+    #[test]
+    fn dates() {
+        let time = chrono::FixedOffset::east_opt(2 * 3600).unwrap().with_ymd_and_hms(2024, 3, 9, 7, 5, 1).unwrap();
+        let date = |format: &str| formatted(&time, date_format(format));
+        assert_eq!(date("date"), Ok("2024-03-09".to_string()));
+        assert_eq!(date("datetime"), Ok("2024-03-09T07:05:01+02:00".to_string()));
+        assert_eq!(date("time"), Ok("07:05:01".to_string()));
+        assert_eq!(date("%d/%m %% %A"), Ok("09/03 % Saturday".to_string()));
+        assert_eq!(date("%Q"), Err("Invalid date format: %Q".to_string()));
+
+        // Inserted at every cursor, which moves past it, in the format of the option when not given
+        let mut editor = buffer("ab\ncd\n", "");
+        editor.set_cursors(&[(0, 1), (1, 1)]);
+        run(&mut editor, "set dateformat=[%%]");
+        run(&mut editor, "date");
+        assert_eq!((editor.rope.to_string(), positions(&editor)), ("a[%]b\nc[%]d\n".to_string(), vec![(0, 4), (1, 4)]));
+        run(&mut editor, "date <%%>");
+        assert_eq!(editor.rope.to_string(), "a[%]<%>b\nc[%]<%>d\n");
+        run(&mut editor, "date %Q");
+        assert_eq!((editor.rope.line(0).to_string(), message(&editor)), ("a[%]<%>b\n".to_string(), Some("Invalid date format: %Q")));
+        // The default is the date and time, of the same length whatever the time
+        let mut editor = buffer("", "");
+        run(&mut editor, "date");
+        assert_eq!(editor.rope.len_chars(), "2024-03-09T07:05:01+02:00".len());
+    }
+
+    #[test]
+    fn cursors_part_after_a_short_line() {
+        let mut editor = buffer("abcdef\nabcdef\nab\nabcdef\nabcdef\n", "");
+        editor.set_cursors(&[(0, 4), (1, 4)]);
+        for _ in 0..3 {
+            editor.key(Key::Down, 1);
+        }
+        assert_eq!(positions(&editor), vec![(3, 4), (4, 4)]);
+        for _ in 0..3 {
+            editor.key(Key::Up, 1);
+        }
+        assert_eq!(positions(&editor), vec![(0, 4), (1, 4)]);
+
+        // Cursors on a line meet at the end of the short one
+        editor.set_cursors(&[(1, 3), (1, 5)]);
+        editor.key(Key::Down, 1);
+        assert_eq!(positions(&editor), vec![(2, 2), (2, 2)]);
+        editor.key(Key::Down, 1);
+        assert_eq!(positions(&editor), vec![(3, 3), (3, 5)]);
+        editor.key(Key::Up, 1);
+        editor.key(Key::Up, 1);
+        assert_eq!(positions(&editor), vec![(1, 3), (1, 5)]);
+
+        // Typing where they meet types once
+        editor.key(Key::Down, 1);
+        editor.key(Key::Char('x'), 1);
+        assert_eq!(editor.rope.line(2), "abx\n");
+        assert_eq!(positions(&editor), vec![(2, 3)]);
+    }
+
+    #[test]
+    fn selected_line_breaks() {
+        let mut editor = buffer("ab\ncd\nef\n", "");
+        editor.key(Key::Right, 1);
+        editor.key(Key::Shift(Nav::Down), 1);
+        assert!(editor.selects_newline(0));
+        assert!(!editor.selects_newline(1));
+        assert!(!editor.selects_newline(2));
+        // Up to the end of the first line, not past its break
+        editor.key(Key::Ctrl('e'), 1);
+        editor.set_cursors(&[(0, 0)]);
+        editor.key(Key::Shift(Nav::Right), 1);
+        editor.key(Key::Shift(Nav::Right), 1);
+        assert!(!editor.selects_newline(0));
+        editor.key(Key::Shift(Nav::Right), 1);
+        assert!(editor.selects_newline(0));
+    }
+
+    #[test]
+    fn trim() {
+        let text = " \t a b \t\n\t  \n  c\t";
+        for (command, trimmed) in [
+            ("trim", "a b\n\nc"),
+            ("trim both", "a b\n\nc"),
+            ("trim start", "a b \t\n\nc\t"),
+            ("trim end", " \t a b\n\n  c"),
+        ] {
+            let mut editor = buffer(text, "");
+            select_all(&mut editor);
+            run(&mut editor, command);
+            assert_eq!(editor.rope.to_string(), trimmed, "{}", command);
+            assert!(editor.dirty);
+        }
+
+        // Cursors and anchors keep to the text they were on
+        let mut editor = buffer("   abc   \n", "");
+        editor.set_cursors(&[(0, 4)]);
+        editor.key(Key::Shift(Nav::Right), 1);
+        editor.key(Key::Shift(Nav::Right), 1);
+        editor.key(Key::Shift(Nav::Right), 1);
+        editor.key(Key::Shift(Nav::Right), 1);
+        run(&mut editor, "trim");
+        assert_eq!(editor.rope.to_string(), "abc\n");
+        assert_eq!(editor.cursors[0].anchor, Some((0, 1)));
+        assert_eq!(positions(&editor), vec![(0, 3)]);
+        assert_eq!(parse_error("trim middle"), "Usage: trim [both|start|end]: trim middle");
+    }
+
+    fn parse_error(command: &str) -> String {
+        Command::parse(command).err().unwrap()
+    }
+
+    #[test]
+    fn escapes() {
+        let strip = strip_escapes;
+        assert_eq!(strip("\x1b[1;31mred\x1b[0m"), ("red".to_string(), 2));
+        assert_eq!(strip("\x1b]0;title\x07a\x1b]8;;file\x1b\\b"), ("ab".to_string(), 2));
+        assert_eq!(strip("\x1b(Bx\x1b)0"), ("x".to_string(), 2));
+        assert_eq!(strip("\x1bOPa\x1bNb"), ("a".to_string(), 2));
+        assert_eq!(strip("\x1b7\x1b8\x1bc"), ("".to_string(), 3));
+        // An ESC that does not start a whole sequence is kept
+        assert_eq!(strip("\x1b]0;title"), ("\x1b]0;title".to_string(), 0));
+        assert_eq!(strip("\x1b]0;a\x1bb"), ("\x1b]0;a\x1bb".to_string(), 0));
+        assert_eq!(strip("\x1b[1;3"), ("\x1b[1;3".to_string(), 0));
+        assert_eq!(strip("a\x1b"), ("a\x1b".to_string(), 0));
+        assert_eq!(strip("\x1b\x1b[m"), ("\x1b".to_string(), 1));
+    }
+
+    // Text of few characters, with the line breaks Rope knows and a character of two bytes
+    const TEXT: &str = "[a \t\u{e9}\r\n]{0,40}";
+
+    proptest! {
+        #[test]
+        fn compare_pairs_as_many_lines_as_a_longest_common_subsequence(a in "[abc\n]{0,24}", b in "[abc\n]{0,24}") {
+            check_compare(&a, &b)?;
+        }
+
+        #[test]
+        fn commenting_twice_changes_nothing(text in "[a \t\n]{0,30}", language in prop::sample::select(vec!["rs", "css", "html", "lua"])) {
+            let mut editor = buffer(&text, language);
+            select_all(&mut editor);
+            editor.key(Key::Alt('/'), 1);
+            editor.key(Key::Alt('/'), 1);
+            prop_assert_eq!(editor.rope.to_string(), text);
+        }
+
+        #[test]
+        fn cells_are_counted_a_character_at_a_time(text in "[ab\t\u{0}\u{e9}\u{4e00}\u{301}]{0,12}", repeat in 1..400usize, start in 0..8usize) {
+            let rope = Rope::from_str(&text.repeat(repeat));
+            let slow = rope.chars().fold(start, |used, c| used + advance(c, used, 4)) - start;
+            prop_assert_eq!(cells(rope.slice(..), start, 4), slow);
+        }
+
+        #[test]
+        fn movements_keep_the_cursor_in_the_buffer(text in TEXT, movements in vec(movement(), 0..40)) {
+            let rope = Rope::from_str(&text);
+            let mut cursor = Cursor::new(0, 0);
+            for movement in movements {
+                cursor.apply(&rope, movement);
+                prop_assert!(cursor.line() <= lines(&rope));
+                prop_assert!(cursor.col(&rope) <= cursor.columns(&rope));
+                prop_assert!(cursor.pos(&rope) <= end(&rope));
+            }
+        }
+
+        #[test]
+        fn left_and_right_undo_each_other(text in TEXT, line in 0..12usize, col in 0..12usize) {
+            let rope = Rope::from_str(&text);
+            let mut cursor = Cursor::new(0, 0);
+            cursor.apply(&rope, Movement::Goto(line, col));
+            let pos = cursor.pos(&rope);
+            if pos > 0 {
+                cursor.apply(&rope, Movement::Left(1));
+                cursor.apply(&rope, Movement::Right(1));
+                prop_assert_eq!(cursor.pos(&rope), pos);
+            }
+            if pos < end(&rope) {
+                cursor.apply(&rope, Movement::Right(1));
+                cursor.apply(&rope, Movement::Left(1));
+                prop_assert_eq!(cursor.pos(&rope), pos);
+            }
+        }
+
+        #[test]
+        fn keys_keep_the_cursors_in_the_buffer(text in TEXT, keys in vec(key(), 0..30), soft_tabs: bool, smart_indent: bool, auto_pairs: bool) {
+            let mut editor = Editor::new(Rope::from_str(&text));
+            editor.options.soft_tabs = soft_tabs;
+            editor.options.smart_indent = smart_indent;
+            editor.options.auto_pairs = auto_pairs;
+            editor.language = Some("rs".to_string());
+            for key in keys {
+                editor.key(key, 3);
+                for cursor in &editor.cursors {
+                    prop_assert!(cursor.line() <= lines(&editor.rope));
+                    prop_assert!(cursor.pos(&editor.rope) <= end(&editor.rope));
+                }
+                prop_assert!(editor.modified.is_empty() || editor.modified.len() == editor.rope.len_lines());
+            }
+        }
+    }
+}
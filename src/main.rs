@@ -1,455 +1,1696 @@
-use std::cmp::min;
-use std::env::args;
-use std::fs::File;
-use std::io::{stdin, stdout, Write};
+use std::cmp::{max, min};
+use std::env::{self, args};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{stdin, stdout, Read, Write};
+use std::mem;
 use std::vec::Vec;
 use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::thread;
 
 use termion::clear;
+use termion::color;
 use termion::cursor;
 use termion::style;
-use termion::event::{Event, Key, MouseEvent, MouseButton};
+use termion::event::{self, Event, MouseEvent, MouseButton};
 use termion::input::{MouseTerminal, TermRead};
 use termion::raw::IntoRawMode;
 use termion::screen;
-use termion::terminal_size;
+use termion::{get_tty, is_tty, terminal_size};
 
 use ropey::Rope;
-use ropey::RopeSlice;
 
-fn lines(rope: &Rope) -> usize {
-    rope.len_lines().saturating_sub(1)
+use ted::{advance, cells, code_point, columns, compare, fit, lines, Command, Editor, Encoding, Key, Mark, Nav};
+
+const HELP: &str = "\
+Usage: ted [options] [file or directory]
+       ted [options] -
+       ted [options] --diff <file> <file>
+
+Text piped to ted, or read from the standard input with -, is edited in a buffer
+without a file name. The indentation, line endings and final newline of files
+follow the .editorconfig files above them, after the configuration and before -c.
+
+Options:
+  -c <command>   Run a command once the buffer is loaded, like with Alt-:
+  -t <extension>
+      Handle a buffer without a file name, like piped text, as a file with
+      this extension in the configuration, e.g. -t json
+  --stdin-filename <name>
+      Same as -t with the extension of the name
+  --encoding <encoding>
+      Read the file or piped text in utf-8, latin1, windows-1252, utf-16le or
+      utf-16be instead of the one found from its bytes
+  --no-color     Draw without colors, also when NO_COLOR is set
+  --no-alt-screen
+      Draw on the screen of the shell, where the buffer stays after quitting
+  --diff <file> <file>
+      Compare two files side by side, read-only, scrolling together and with
+      Alt-m, Alt-M going to the next, previous difference
+  -h, --help     Print this help
+  -V, --version  Print the version
+
+Keys:
+  Ctrl-s            Save, or save as when the buffer has no file
+  Ctrl-q            Quit
+  Ctrl-g            Go to line, N% of the buffer, or byte N counted from 0
+  Alt-:             Command prompt, Up, Down for the commands entered before
+  Alt-|             Filter the selection, or the buffer, through a command
+  Ctrl-a, Ctrl-e    Start, end of line
+  Home, End         Start, end of buffer
+  PageUp, PageDown  Previous, next page
+  Ctrl-u, Ctrl-d    Half a page up, down
+  Shift-<movement>  Select
+  Alt-x             Go to the other end of the selection
+  Alt-b, Alt-B      Select to the matching bracket, with or without the brackets
+  Alt-*, Alt-#      Next, previous occurrence of the word under the cursor
+  Alt-e, Alt-E      Next misspelled word, suggestions for the word under the cursor
+  Alt-m, Alt-M      Next, previous changed lines, since saved or from git
+  Ctrl-k, Ctrl-y    Delete lines, paste them, or what Alt-y swapped out
+  Alt-y             Swap the selection, or the character, with the deleted lines
+  Ctrl-o            Break the line, staying before the break
+  Alt-d             Duplicate the selection, or the line
+  Alt-J             Join the selected lines, or the line and the next, with a delimiter
+  Alt-~             Toggle the case of the selection, or of the character, moving past it
+  Alt-/, Ctrl-/     Comment or uncomment the lines, or a selection within lines
+  Ctrl-v            Insert a character by its code point in hex
+  Alt-o, Alt-u      Open a line below, above
+  Alt-j, Alt-k      Add a cursor above, below
+  Alt-drag          Put a cursor on each line dragged over, at the same column
+  Alt-z, Alt-Z      Fold the lines indented under the line, unfold all
+  Alt-t             Pin the cursor, kept by Esc and left in place by movements
+  Esc               Back to a single cursor, the pinned one if any
+  Alt-<digits>      Repeat the next key
+  Alt-.             Repeat the last change
+  Alt-q, Alt-@      Record a macro, play it
+  Insert            Overwrite mode
+  F2                Paste mode
+  Alt-g             Open the path under the cursor
+  Ctrl-n            New buffer
+  Alt-n, Alt-p      Next, previous buffer
+  Alt-s, Alt-v      Split stacked, side by side
+  Alt-w, Alt-c      Next pane, close pane
+  Alt-=, Alt--      Grow, shrink the pane
+  Alt-f             Text only, without the gutter and the status rows
+";
+
+// Converts a key read by termion to the one handled by the editor
+fn translate(key: event::Key) -> Key {
+    match key {
+        event::Key::Backspace => Key::Backspace,
+        event::Key::Left => Key::Left,
+        event::Key::Right => Key::Right,
+        event::Key::Up => Key::Up,
+        event::Key::Down => Key::Down,
+        event::Key::Home => Key::Home,
+        event::Key::End => Key::End,
+        event::Key::PageUp => Key::PageUp,
+        event::Key::PageDown => Key::PageDown,
+        event::Key::BackTab => Key::BackTab,
+        event::Key::Delete => Key::Delete,
+        event::Key::Insert => Key::Insert,
+        event::Key::F(n) => Key::F(n),
+        event::Key::Char(c) => Key::Char(c),
+        event::Key::Alt(c) => Key::Alt(c),
+        event::Key::Ctrl(c) => Key::Ctrl(c),
+        event::Key::Esc => Key::Esc,
+        _ => Key::Null,
+    }
 }
 
-fn columns(line: RopeSlice) -> usize {
-    let max = line.len_chars();
-    if max > 0 && line.char(max - 1) == '\n'
-    { max - 1 } else { max }
+// Reads the sequences of movement keys held with modifiers, which termion does not know
+// about: xterm's `CSI 1;<modifiers><letter>` and `CSI <number>;<modifiers>~`, and the arrows
+// of rxvt, `CSI <lowercase letter>` with Shift and `SS3 <lowercase letter>` with Ctrl
+fn modified(bytes: &[u8]) -> Option<Key> {
+    let arrow = |c: u8| match c.to_ascii_uppercase() {
+        b'A' => Some(Nav::Up),
+        b'B' => Some(Nav::Down),
+        b'C' => Some(Nav::Right),
+        b'D' => Some(Nav::Left),
+        _ => None,
+    };
+    match bytes {
+        [0x1b, b'[', c @ b'a'..=b'd'] => return arrow(*c).map(Key::Shift),
+        [0x1b, b'O', c @ b'a'..=b'd'] => return arrow(*c).map(Key::CtrlNav),
+        [0x1b, b'[', ..] => {}
+        _ => return None,
+    }
+
+    let (last, params) = bytes[2..].split_last()?;
+    let params = std::str::from_utf8(params).ok()?;
+    let (number, modifiers) = params.split_once(';')?;
+    let nav = match (number, last) {
+        ("1", b'H') => Nav::Home,
+        ("1", b'F') => Nav::End,
+        ("1", b'~') | ("7", b'~') => Nav::Home,
+        ("1", &c) if c.is_ascii_uppercase() => arrow(c)?,
+        ("4", b'~') | ("8", b'~') => Nav::End,
+        ("5", b'~') => Nav::PageUp,
+        ("6", b'~') => Nav::PageDown,
+        _ => return None,
+    };
+    // 1 plus the sum of 1 for Shift, 2 for Alt and 4 for Ctrl
+    match modifiers {
+        "2" => Some(Key::Shift(nav)),
+        "3" => Some(Key::AltNav(nav)),
+        "4" => Some(Key::AltShift(nav)),
+        "5" => Some(Key::CtrlNav(nav)),
+        "6" => Some(Key::CtrlShift(nav)),
+        _ => None,
+    }
 }
 
-fn end(rope: &Rope) -> usize {
-    let line = lines(rope);
-    rope.line_to_char(line) + columns(rope.line(line))
+// An event as the loop handles it, with keys as the editor takes them, those read from
+// sequences termion does not know about included
+enum Input {
+    Key(Key),
+    Mouse(MouseEvent),
+    Bytes(Vec<u8>),
 }
 
-struct Cursor {
-    line: usize,
-    col: usize,
+impl From<Event> for Input {
+    fn from(evt: Event) -> Self {
+        match evt {
+            Event::Key(key) => Input::Key(translate(key)),
+            Event::Mouse(mouse) => Input::Mouse(mouse),
+            Event::Unsupported(bytes) => match modified(&bytes) {
+                Some(key) => Input::Key(key),
+                None => Input::Bytes(bytes),
+            },
+        }
+    }
+}
+
+// Reads a press or drag of the left button with Alt held, which termion does not parse,
+// from the sequence of the SGR mouse mode
+fn alt_mouse(bytes: &[u8]) -> Option<MouseEvent> {
+    let params = std::str::from_utf8(bytes.strip_prefix(b"\x1b[<")?.strip_suffix(b"M")?).ok()?;
+    let mut params = params.split(';').map(|param| param.parse::<u16>().ok());
+    let (button, x, y) = (params.next()??, params.next()??, params.next()??);
+    // 8 is added for Alt and 32 for motion
+    match button {
+        8 => Some(MouseEvent::Press(MouseButton::Left, x, y)),
+        40 => Some(MouseEvent::Hold(x, y)),
+        _ => None,
+    }
+}
+
+enum Action {
+    SaveAs,
+    // Line and column of the cursor, and first line of the view, when the prompt was opened
+    Goto { line: usize, col: usize, top: usize },
+    Command,
+    Unicode,
+    // Command replacing text all over the buffer, run once answered with y
+    Confirm(Command),
+    // Key editing at more cursors than the limit, handled once answered with y
+    Edit(Key),
+}
+
+struct Prompt {
+    label: String,
+    input: String,
+    action: Action,
+    // Entry of the history shown in place of the input, and the input typed before
+    recalled: Option<usize>,
+    typed: String,
+}
+
+impl Prompt {
+    fn new(label: &str, action: Action) -> Self {
+        Self { label: label.to_string(), input: String::new(), action, recalled: None, typed: String::new() }
+    }
+
+    // Returns Some(true) if the prompt was validated, Some(false) if it was cancelled
+    fn key(&mut self, key: Key, history: &History) -> Option<bool> {
+        match key {
+            Key::Char(c) if matches!(self.action, Action::Confirm(_) | Action::Edit(_)) => Some(c == 'y' || c == 'Y'),
+            Key::Up | Key::Down if matches!(self.action, Action::Command) => { self.recall(history, key == Key::Up); None }
+            Key::Char('\n') => Some(true),
+            Key::Esc | Key::Ctrl('c') => Some(false),
+            Key::Char(c) => { self.input.push(c); None }
+            Key::Backspace => { self.input.pop(); None }
+            _ => None,
+        }
+    }
+
+    // Shows an older or newer entry of the history in place of the input, and the input
+    // typed before past the newest one
+    fn recall(&mut self, history: &History, older: bool) {
+        let count = history.entries.len();
+        let recalled = match (self.recalled, older) {
+            (None, true) if count > 0 => {
+                self.typed = mem::take(&mut self.input);
+                count - 1
+            }
+            (Some(index), true) => index.saturating_sub(1),
+            (Some(index), false) if index + 1 < count => index + 1,
+            (Some(_), false) => {
+                self.recalled = None;
+                self.input = mem::take(&mut self.typed);
+                return;
+            }
+            _ => return,
+        };
+        self.recalled = Some(recalled);
+        self.input = history.entries[recalled].clone();
+    }
 }
 
-enum Movement {
-    Up(usize),
-    Down(usize),
-    Left(usize),
-    Right(usize),
-    Begin,
-    End,
-    LineBegin,
-    LineEnd,
-    Goto(usize, usize),
-    GotoLine(usize),
-    GotoCol(usize),
+// Commands entered at the prompt, oldest first and as many as `HISTORY_SIZE`, kept across
+// sessions in a file of the state directory as long as it can be read and written
+struct History {
+    entries: Vec<String>,
+    path: Option<PathBuf>,
 }
 
-impl Cursor {
-    fn new(line: usize, col: usize) -> Self {
-        Self { line, col }
+const HISTORY_SIZE: usize = 200;
+
+impl History {
+    fn path() -> Option<PathBuf> {
+        env::var_os("XDG_STATE_HOME").map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local").join("state")))
+            .map(|directory| directory.join("ted").join("history"))
     }
 
-    fn line(&self) -> usize {
-        self.line
+    fn load() -> Self {
+        Self::at(Self::path())
     }
 
-    fn columns(&self, rope: &Rope) -> usize {
-        columns(rope.line(self.line))
+    // The history kept in a file, empty when there is none or it cannot be read
+    fn at(path: Option<PathBuf>) -> Self {
+        let text = path.as_ref().and_then(|path| fs::read_to_string(path).ok()).unwrap_or_default();
+        let mut entries = text.lines().filter(|line| !line.trim().is_empty()).map(String::from).collect::<Vec<String>>();
+        entries.drain(..entries.len().saturating_sub(HISTORY_SIZE));
+        Self { entries, path }
     }
 
-    fn col(&self, rope: &Rope) -> usize {
-        min(self.col, self.columns(rope))
+    // Adds an entry, unless it is the same as the last one, and saves the history again
+    fn push(&mut self, entry: &str) {
+        if entry.trim().is_empty() || self.entries.last().map(String::as_str) == Some(entry) {
+            return;
+        }
+        self.entries.push(entry.to_string());
+        self.entries.drain(..self.entries.len().saturating_sub(HISTORY_SIZE));
+        if let Some(path) = &self.path {
+            // Kept for the rest of the session when it cannot be saved
+            let _ = path.parent().map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| fs::write(path, self.entries.join("\n") + "\n"));
+        }
     }
+}
 
-    fn pos(&self, rope: &Rope) -> usize {
-        rope.line_to_char(self.line) + self.col(rope)
+// A view over a buffer, drawn above its own status row
+struct Pane {
+    buffer: usize,
+    // Scroll offsets
+    x: usize,
+    y: usize,
+    // Position on screen and size, not counting the status row
+    top: usize,
+    left: usize,
+    height: usize,
+    width: usize,
+    // Drawn with the text alone, over the status row too, which only shows prompts and messages
+    bare: bool,
+    // Rows, with the status row, or columns taken in the layout
+    size: usize,
+}
+
+impl Pane {
+    // Columns left of the text, where changed lines are marked while the buffer has some
+    fn gutter(&self, editor: &Editor) -> usize {
+        if self.bare || !editor.options.gutter_marks || editor.modified.is_empty() && editor.hunks.is_empty() {
+            0
+        } else {
+            editor.options.gutter_width()
+        }
     }
 
-    fn apply(&mut self, rope: &Rope, movement: Movement) {
-        match movement {
-            Movement::Up(n) => {
-                self.line = self.line.saturating_sub(n);
-            }
-            Movement::Down(n) => {
-                self.line = if self.line + n >= lines(rope) { lines(rope) } else { self.line + n };
+    // Draws the gutter of a row, a mark between the padding and then the separator
+    fn margin<W>(&self, w: &mut W, editor: &Editor, mark: char, fg: &str)
+    where
+        W: Write,
+    {
+        let (before, after) = editor.options.gutter_padding;
+        write!(w, "{:before$}{}{}{}{:after$}", "", fg, mark, style::Reset, "", before = before, after = after).unwrap();
+        if !editor.options.gutter_separator.is_empty() {
+            write!(w, "{}{}{}", style::Faint, editor.options.gutter_separator, style::Reset).unwrap();
+        }
+    }
+
+    // Column right of the text with the overview of the buffer, when turned on and there is room.
+    // Rows of a buffer compared with another are not lines, they have no overview.
+    fn minimap(&self, editor: &Editor) -> usize {
+        if editor.options.minimap && !self.bare && self.width > 20 && editor.aligned.is_none() { 1 } else { 0 }
+    }
+
+    // Columns the text is drawn in
+    fn text_width(&self, editor: &Editor) -> usize {
+        self.width.saturating_sub(self.gutter(editor) + self.minimap(editor)).max(1)
+    }
+
+    // Lines of the buffer summed up by a row of the overview, as a range. Without more
+    // lines than rows, each row is a line.
+    fn summed(&self, editor: &Editor, row: usize) -> (usize, usize) {
+        let count = editor.rope.len_lines();
+        if count <= self.height {
+            (row, row + 1)
+        } else {
+            (row * count / self.height, (row + 1) * count / self.height)
+        }
+    }
+
+    // Cells taken by the columns of a line left of the view, from which its tab stops are counted
+    fn lead(&self, editor: &Editor, index: usize) -> usize {
+        let line = editor.rope.line(index);
+        cells(line.slice(..min(self.x, columns(line))), 0, editor.options.tab_width)
+    }
+
+    // Column of a line under a cell of the text of the pane
+    fn column(&self, editor: &Editor, index: usize, cell: usize) -> usize {
+        let line = editor.rope.line(index);
+        let max = columns(line);
+        self.x + fit(line.slice(min(self.x, max)..max), self.lead(editor, index), cell, editor.options.tab_width)
+    }
+
+    // Cell of the text and row of the pane nearest to a cell of the screen, counted from 1,
+    // for a drag that goes past the pane to stay within it
+    fn cell(&self, editor: &Editor, x: u16, y: u16) -> (usize, usize) {
+        let x = min((x as usize - 1).saturating_sub(self.left + self.gutter(editor)), self.text_width(editor) - 1);
+        let y = min((y as usize - 1).saturating_sub(self.top), self.height - 1);
+        (x, y)
+    }
+
+    // Line on a row of the pane, the next one shown below a filler row of a buffer compared
+    // with another, or the last one past them
+    fn line_at(&self, editor: &Editor, row: usize) -> usize {
+        match &editor.aligned {
+            Some((rows, _)) => min(rows.partition_point(|&at| at < self.y + row), lines(&editor.rope)),
+            None => editor.below(self.y, row),
+        }
+    }
+
+    // Keeps the view on the buffer, which may have lost lines to edits made in another pane
+    fn clamp(&mut self, editor: &Editor) {
+        if editor.aligned.is_none() {
+            self.y = min(self.y, lines(&editor.rope));
+        }
+    }
+
+    // Scrolls to keep the primary cursor visible, returns whether it did.
+    // Lines hidden by folds take no rows and are not counted.
+    fn follow(&mut self, editor: &Editor) -> bool {
+        let mut need_update = false;
+        let y = self.y;
+        let off = min(editor.options.scroll_off, self.height.saturating_sub(1) / 2);
+        if let Some((rows, count)) = &editor.aligned {
+            // The view of a buffer compared with another scrolls over its rows
+            let row = rows[editor.line()];
+            if row < self.y + off {
+                self.y = row.saturating_sub(off);
             }
-            Movement::Left(n) => {
-                self.col = self.col(rope);
-                for _ in 0..n {
-                    if self.col > 0 {
-                        self.col -= 1;
-                    } else if self.line > 0 {
-                        self.line -= 1;
-                        self.col = self.columns(rope);
-                    } else {
-                        break
-                    }
-                }
+            if row + off >= self.y + self.height {
+                self.y = row + off + 1 - self.height;
             }
-            Movement::Right(n) => {
-                self.col = self.col(rope);
-                for _ in 0..n {
-                    if self.col < self.columns(rope) {
-                        self.col += 1;
-                    } else if self.line < lines(rope) {
-                        self.line += 1;
-                        self.col = 0;
-                    } else {
-                        break
-                    }
-                }
+            let end = if editor.options.scroll_past_end { count - 1 } else { count.saturating_sub(self.height) };
+            self.y = min(self.y, max(end, row.saturating_sub(off)));
+        } else {
+            self.y = editor.shown(self.y);
+            if editor.line() < self.y || editor.rows(self.y, editor.line()) < off {
+                self.y = editor.above(editor.line(), off);
             }
-            Movement::LineBegin => {
-                if self.col == 0 { self.apply(rope, Movement::Up(1)) }
-                else {  self.col = 0 }
+
+            // No margin is kept below the last line, unless the view scrolls past it where
+            // the empty rows make up the margin
+            let bottom = editor.below(editor.line(), off);
+            let past = if editor.options.scroll_past_end { off - editor.rows(editor.line(), bottom) } else { 0 };
+            if editor.rows(self.y, bottom + 1) + past > self.height {
+                self.y = editor.above(bottom, self.height - 1 - past);
             }
-            Movement::LineEnd => {
-                if self.col >= self.columns(rope) {
-                    self.apply(rope, Movement::Down(1))
+            let last = lines(&editor.rope);
+            let end = if editor.options.scroll_past_end { editor.shown(last) } else { editor.above(last, self.height - 1) };
+            self.y = min(self.y, end);
+        }
+        if self.y != y {
+            need_update = true;
+        }
+
+        if editor.col() < self.x {
+            self.x = editor.col();
+            need_update = true;
+        }
+
+        let text_width = self.text_width(editor);
+        if editor.col() >= self.x + text_width {
+            self.x = editor.col() - text_width + 1;
+            need_update = true;
+        }
+
+        // Wide characters and tabs use more cells, scroll further until the cursor cell fits
+        let line = editor.rope.line(editor.line());
+        let tab_width = editor.options.tab_width;
+        let span = |x: usize, lead: usize| match editor.col() < columns(line) {
+            true => cells(line.slice(x..editor.col() + 1), lead, tab_width),
+            false => cells(line.slice(x..editor.col()), lead, tab_width) + 1,
+        };
+        let mut lead = cells(line.slice(..self.x), 0, tab_width);
+        while self.x < editor.col() && span(self.x, lead) > text_width {
+            lead += advance(line.char(self.x), lead, tab_width);
+            self.x += 1;
+            need_update = true;
+        }
+        need_update
+    }
+
+    // Draws each row on its own, over the cells it had, so that the rows left as they
+    // were can be left out of a frame
+    fn draw(&self, editor: &Editor, color: bool, active: bool) -> Vec<Vec<u8>> {
+        let mut rows = Vec::with_capacity(self.height);
+        // Only index the visible lines and columns, so that the cost of a frame
+        // does not depend on the size of the buffer or the length of its lines
+        let mut ln = Some(editor.shown(self.y));
+        for row in 0..self.height {
+            let mut w = Vec::with_capacity(self.width * 2);
+            self.erase(&mut w, row);
+            // Rows of a buffer compared with another show its lines, or fillers facing the
+            // lines only in the other one
+            let index = match (&editor.aligned, ln) {
+                (Some((aligned, count)), _) if self.y + row < *count => match aligned.binary_search(&(self.y + row)) {
+                    Ok(index) => index,
+                    Err(_) => {
+                        let gutter = self.gutter(editor);
+                        if gutter > 0 {
+                            self.margin(&mut w, editor, ' ', "");
+                        }
+                        let fill = "╱".repeat(self.width.saturating_sub(gutter));
+                        write!(w, "{}{}{}", style::Faint, fill, style::Reset).unwrap();
+                        rows.push(w);
+                        continue;
+                    }
+                },
+                (None, Some(index)) => index,
+                // Rows past the end of the buffer are marked, as in vi
+                _ => {
+                    if !self.bare {
+                        write!(w, "{}~{}", style::Faint, style::Reset).unwrap();
+                    }
+                    rows.push(w);
+                    continue;
                 }
-                self.col = self.columns(rope);
+            };
+            // Lines changed since the last save are marked over the changes from the git index
+            if self.gutter(editor) > 0 {
+                let (mark, fg) = match (editor.modified.get(index), editor.hunk(index)) {
+                    (Some(true), _) => ('•', color::Fg(color::Blue).to_string()),
+                    (_, Some(Mark::Added)) => ('+', color::Fg(color::Green).to_string()),
+                    (_, Some(Mark::Changed)) => ('~', color::Fg(color::Yellow).to_string()),
+                    (_, Some(Mark::Deleted)) | (_, Some(Mark::Removed)) => ('-', color::Fg(color::Red).to_string()),
+                    _ => (' ', String::new()),
+                };
+                self.margin(&mut w, editor, mark, if color { &fg } else { "" });
             }
-            Movement::Begin => {
-                self.line = 0;
-                self.col = 0;
+            let text_width = self.text_width(editor);
+            let cells = self.line(&mut w, editor, index, color, active);
+
+            // A fold is summed up after the line it is under
+            if let Some(count) = editor.folded(index) {
+                let summary = format!(" ⋯ {} line{}", count, if count == 1 { "" } else { "s" });
+                let summary = summary.chars().take(text_width.saturating_sub(cells)).collect::<String>();
+                write!(w, "{}{}{}", style::Faint, summary, style::Reset).unwrap();
             }
-            Movement::End => {
-                self.line = lines(rope);
-                self.col = self.columns(rope);
+
+            rows.push(w);
+            let next = editor.below(index, 1);
+            ln = if next == index { None } else { Some(next) };
+        }
+
+        if self.minimap(editor) > 0 {
+            self.overview(&mut rows, editor, color);
+        }
+        rows
+    }
+
+    // Moves to the start of a row, relative to the top of the pane, and blanks its cells
+    // with the styles reset, without moving on (ECH)
+    fn erase<W: Write>(&self, w: &mut W, row: usize) {
+        let goto = cursor::Goto(self.left as u16 + 1, (self.top + row) as u16 + 1);
+        write!(w, "{}{}\x1b[{}X", goto, style::Reset, self.width).unwrap();
+    }
+
+    // Draws a shade per row for how full the lines it sums up are, over a background
+    // where they are in view. A few lines are sampled per row, however large the buffer.
+    fn overview(&self, rows: &mut [Vec<u8>], editor: &Editor, color: bool) {
+        const SHADES: [char; 5] = [' ', '░', '▒', '▓', '█'];
+        const SAMPLES: usize = 4;
+        let count = editor.rope.len_lines();
+        let (first, last) = (editor.shown(self.y), editor.below(self.y, self.height - 1));
+        let view = if color { color::Bg(color::LightBlack).to_string() } else { style::Invert.to_string() };
+        for (row, w) in rows.iter_mut().enumerate() {
+            let (start, end) = self.summed(editor, row);
+            let end = min(end, count);
+            let step = max(1, (end.saturating_sub(start)) / SAMPLES);
+            let sampled = (start..end).step_by(step).take(SAMPLES)
+                .map(|line| min(columns(editor.rope.line(line)), 80)).collect::<Vec<usize>>();
+            let shade = match sampled.len() {
+                0 => ' ',
+                len => SHADES[min((sampled.iter().sum::<usize>() / len).div_ceil(20), 4)],
+            };
+            let x = self.left + self.width - 1;
+            write!(w, "{}", cursor::Goto(x as u16 + 1, (self.top + row) as u16 + 1)).unwrap();
+            if start <= last && first < end.max(start + 1) {
+                write!(w, "{}{}{}", view, shade, style::Reset).unwrap();
+            } else {
+                write!(w, "{}", shade).unwrap();
             }
-            Movement::Goto(line, col) => {
-                self.line = min(line, lines(rope));
-                self.col = min(col, self.columns(rope));
+        }
+    }
+
+    // Draws the visible part of a line, starting at column `self.x`, returns the cells used.
+    // Without colors, selections are underlined and the line jumped to is bold. While the
+    // terminal is not focused, the text is dimmed and the cursors are only underlined.
+    fn line<W>(&self, w: &mut W, editor: &Editor, index: usize, color: bool, active: bool) -> usize
+    where
+        W: Write,
+    {
+        // The cells of the line left of the view are counted once, for the tab stops
+        let lead = self.lead(editor, index);
+        let line = editor.rope.line(index);
+        let max = columns(line);
+        let line = line.slice(min(self.x, max)..max);
+        let line = line.slice(..fit(line, lead, self.text_width(editor), editor.options.tab_width));
+        // Cursors right of the visible part are off screen, not at its end
+        let cursors = editor.cursors_at(index)
+            .into_iter().filter(|&col| col >= self.x && col - self.x <= line.len_chars())
+            .map(|col| col - self.x).collect::<Vec<usize>>();
+        let pinned = editor.pinned_at(index).into_iter().filter(|&col| col >= self.x).map(|col| col - self.x).collect::<Vec<usize>>();
+        let selections = editor.selections_at(index);
+        let misspelled = editor.misspelled(index);
+        let highlights = editor.highlights_at(index);
+        // Column of the first character past the line length, without counting more of the line
+        let long = match editor.options.max_line_length {
+            0 => usize::MAX,
+            cells => fit(editor.rope.line(index), 0, cells, editor.options.tab_width),
+        };
+
+        // Guides are drawn on the indentation at each tab stop, which tabs take up to theirs
+        let tab_width = editor.options.tab_width.max(1);
+        let indent = if editor.options.indent_guides {
+            let whole = editor.rope.line(index);
+            cells(whole.slice(..whole.chars().take_while(|&c| c == ' ' || c == '\t').count()), 0, tab_width)
+        } else { 0 };
+
+        // A line just jumped to is drawn over a background across the pane, restored after
+        // each styled cell, as are the lines that differ from a buffer compared with
+        let flashed = matches!(editor.flash, Some((line, _)) if line == index);
+        let differs = if editor.aligned.is_some() && color { editor.hunk(index) } else { None };
+        let base = match (flashed, color, differs) {
+            (true, true, _) => color::Bg(color::LightBlack).to_string(),
+            (true, false, _) => style::Bold.to_string(),
+            (false, _, Some(Mark::Added)) => color::Bg(color::Green).to_string(),
+            (false, _, Some(Mark::Removed)) => color::Bg(color::Red).to_string(),
+            (false, _, Some(_)) => color::Bg(color::Yellow).to_string(),
+            (false, _, None) => String::new(),
+        };
+        let base = if active { base } else { format!("{}{}", base, style::Faint) };
+        let cursor = if active { style::Invert.to_string() } else { style::Underline.to_string() };
+        let pin = if color { format!("{}{}", cursor, color::Fg(color::Magenta)) } else { format!("{}{}", cursor, style::Bold) };
+        let selected = if color { color::Bg(color::Blue).to_string() } else { style::Underline.to_string() };
+        let wrong = if color { format!("{}{}", style::Underline, color::Fg(color::Red)) } else { style::Underline.to_string() };
+        let over = if color { color::Bg(color::Red).to_string() } else { style::Underline.to_string() };
+        let same = if color { color::Bg(color::LightBlack).to_string() } else { style::Bold.to_string() };
+
+        // Tabs are drawn as the spaces up to the next tab stop, counted from the start of the line
+        write!(w, "{}", base).unwrap();
+        let mut cells = 0;
+        for (i, c) in line.chars().enumerate() {
+            let col = self.x + i;
+            let at = lead + cells;
+            let n = advance(c, at, tab_width);
+            cells += n;
+            let c = if c == '\t' { " ".repeat(n) } else { c.to_string() };
+            if cursors.binary_search(&i).is_ok() {
+                let cursor = if pinned.contains(&i) { &pin } else { &cursor };
+                write!(w, "{}{}{}{}", cursor, c, style::Reset, base).unwrap();
+            } else if selections.iter().any(|&(start, end)| start <= col && col < end) {
+                write!(w, "{}{}{}{}", selected, c, style::Reset, base).unwrap();
+            } else if col >= long {
+                write!(w, "{}{}{}{}", over, c, style::Reset, base).unwrap();
+            } else if misspelled.iter().any(|&(start, end)| start <= col && col < end) {
+                write!(w, "{}{}{}{}", wrong, c, style::Reset, base).unwrap();
+            } else if highlights.iter().any(|&(start, end)| start <= col && col < end) {
+                write!(w, "{}{}{}{}", same, c, style::Reset, base).unwrap();
+            } else if at < indent && at.is_multiple_of(tab_width) {
+                write!(w, "{}│{}{}{}", style::Faint, style::Reset, base, &c[1..]).unwrap();
+            } else {
+                write!(w, "{}", c).unwrap();
             }
-            Movement::GotoLine(line) => {
-                self.line = min(line, lines(rope));
+        }
+
+        // A cursor past the last character gets a cell of its own, when the end of
+        // the line is visible and leaves room for it, as does a line break selected
+        let visible = self.x + line.len_chars() == columns(editor.rope.line(index));
+        let text_width = self.text_width(editor);
+        let end = cells;
+        if visible && cells < text_width && cursors.last() == Some(&line.len_chars()) {
+            let cursor = if pinned.contains(&line.len_chars()) { &pin } else { &cursor };
+            write!(w, "{} {}{}", cursor, style::Reset, base).unwrap();
+            cells += 1;
+        } else if visible && cells < text_width && editor.options.select_newline && editor.selects_newline(index) {
+            write!(w, "{} {}{}", selected, style::Reset, base).unwrap();
+            cells += 1;
+        }
+        // The column a cursor past the end goes back to on longer lines is marked faintly
+        if visible && editor.options.desired_column {
+            for col in editor.desired_at(index) {
+                let at = end + col - (self.x + line.len_chars());
+                if at >= cells && at < text_width {
+                    write!(w, "{:width$}{}·{}{}", "", style::Faint, style::Reset, base, width = at - cells).unwrap();
+                    cells = at + 1;
+                }
             }
-            Movement::GotoCol(col) => {
-                self.col = min(col, self.columns(rope));
+        }
+        if flashed || differs.is_some() {
+            write!(w, "{:width$}{}", "", style::Reset, width = text_width.saturating_sub(cells)).unwrap();
+            cells = text_width;
+        }
+        cells
+    }
+
+    fn status<W>(&self, w: &mut W, editor: &Editor, prompt: Option<&Prompt>, focused: bool)
+    where
+        W: Write,
+    {
+        // Without a row of its own, the status is drawn over the last row of text when there
+        // is something to tell
+        if self.bare && (!focused || prompt.is_none() && editor.message.is_none()) {
+            return;
+        }
+        self.erase(w, if self.bare { self.height - 1 } else { self.height });
+        if let (Some(prompt), true) = (prompt, focused) {
+            let text = format!("{}{}", prompt.label, prompt.input);
+            let text = text.chars().take(self.width.saturating_sub(1)).collect::<String>();
+            write!(w, "{}{} {}", text, style::Invert, style::Reset).unwrap();
+        } else {
+            let left = match &editor.message {
+                Some((message, _)) if focused => format!(" {}", message),
+                _ => format!(" {}{}{}{}{}{}{}{}", editor.name(),
+                             if editor.dirty { " [+]" } else { "" },
+                             if editor.readonly { " [read-only]" } else { "" },
+                             if editor.paste_mode { " [paste]" } else { "" },
+                             if editor.overwrite { " [overwrite]" } else { "" },
+                             if editor.recording.is_some() { " [recording]" } else { "" },
+                             if editor.encoding == Encoding::Utf8 { String::new() } else { format!(" [{}]", editor.encoding.name()) },
+                             editor.saving().map(|percent| format!(" [saving {}%]", percent)).unwrap_or_default()),
+            };
+            // First column shown out of those of the line, when the line does not fit
+            let length = columns(editor.rope.line(editor.line()));
+            let shown = if self.x > 0 || length > self.text_width(editor) { format!("col {}/{}  ", self.x + 1, length) } else { String::new() };
+            let indent = if editor.options.soft_tabs {
+                format!("spaces:{}", editor.options.indent_width())
+            } else {
+                format!("tabs:{}", editor.options.tab_width)
+            };
+            let shown = format!("{}{}  ", shown, indent);
+            let right = if let Some(count) = editor.count {
+                format!("{}{} {}:{} ", shown, count, editor.line() + 1, editor.col() + 1)
+            } else {
+                format!("{}{}:{} ", shown, editor.line() + 1, editor.col() + 1)
+            };
+            let padding = self.width.saturating_sub(left.chars().count() + right.chars().count());
+            let text = format!("{}{:padding$}{}", left, "", right, padding = padding);
+            let text = text.chars().take(self.width).collect::<String>();
+            if focused {
+                write!(w, "{}{}{}{}", style::Invert, style::Bold, text, style::Reset).unwrap();
+            } else {
+                write!(w, "{}{}{}", style::Invert, text, style::Reset).unwrap();
             }
         }
     }
 }
 
-struct Editor {
-    pub rope: Rope,
-    pub cursors: Vec<Cursor>,
+// Cursor shapes, https://invisible-island.net/xterm/ctlseqs/ctlseqs.html
+#[derive(Clone, Copy, PartialEq)]
+enum Shape {
+    Default = 0,
+    Block = 2,
+    Underline = 4,
+    Bar = 6,
 }
 
-impl Editor {
-    fn new(rope: Rope) -> Self {
-        let mut editor = Self {
-            rope,
-            cursors: Vec::with_capacity(4),
-        };
+#[derive(Clone, Copy, PartialEq)]
+enum Layout {
+    Stacked,
+    SideBySide,
+}
 
-        editor.cursors.push(Cursor::new(0 ,0));
-        editor
-    }
+struct TermRenderer {
+    panes: Vec<Pane>,
+    layout: Layout,
+    focus: usize,
+    height: usize,
+    width: usize,
+    // Whether to draw with colors, other styles are kept without them
+    color: bool,
+    // Cursor shape last set on the terminal
+    shape: Shape,
+    // Whether the terminal has the focus, as last reported by it
+    active: bool,
+    // Whether the panes are drawn bare, for the most room for the text
+    zen: bool,
+    // Rows last written and the dividers between panes, each drawn on its own,
+    // emptied when the panes are laid out again
+    frame: Vec<Vec<u8>>,
+    // What the last frame was drawn from, a frame from the same being left out
+    drawn: Option<u64>,
+}
 
-    fn line(&self) -> usize {
-        if let Some(cursor) = self.cursors.first() { cursor.line() } else { 0 }
+impl TermRenderer {
+    fn new(color: bool) -> Self {
+        let (width, height) = terminal_size().unwrap();
+        Self::sized(color, width, height)
     }
 
-    fn col(&self) -> usize {
-        if let Some(cursor) = self.cursors.first() { cursor.col(&self.rope) } else { 0 }
+    fn sized(color: bool, width: u16, height: u16) -> Self {
+        let mut renderer = Self {
+            panes: vec![Pane { buffer: 0, x: 0, y: 0, top: 0, left: 0, height: 0, width: 0, bare: false, size: 0 }],
+            layout: Layout::Stacked,
+            focus: 0,
+            height: height as usize,
+            width: width as usize,
+            color,
+            shape: Shape::Default,
+            active: true,
+            zen: false,
+            frame: Vec::new(),
+            drawn: None,
+        };
+        renderer.layout();
+        renderer
     }
 
-    fn key(&mut self, key: Key, height: usize) -> bool {
-        match key {
-            Key::Up => {
-                for cursor in &mut self.cursors {
-                    cursor.apply(&self.rope, Movement::Up(1));
-                }
-                false
+    // Lays out the panes, stacked with each followed by its status row, or side by side and
+    // separated by a divider column. Bare panes have no status row. The screen is shared
+    // equally again when the sizes of the panes do not add up to it, after a split or a close.
+    fn layout(&mut self) {
+        let count = self.panes.len();
+        let status = if self.zen { 0 } else { 1 };
+        self.frame.clear();
+        self.drawn = None;
+        let room = self.room();
+        if self.panes.iter().map(|pane| pane.size).sum::<usize>() != room || self.panes.iter().any(|pane| pane.size == 0) {
+            for (i, pane) in self.panes.iter_mut().enumerate() {
+                pane.size = room / count + if i < room % count { 1 } else { 0 };
             }
-            Key::Down => {
-                for cursor in &mut self.cursors {
-                    cursor.apply(&self.rope, Movement::Down(1));
-                }
-                false
-            }
-            Key::Left => {
-                for cursor in &mut self.cursors {
-                    cursor.apply(&self.rope, Movement::Left(1));
-                }
-                false
-            }
-            Key::Right => {
-                for cursor in &mut self.cursors {
-                    cursor.apply(&self.rope, Movement::Right(1));
-                }
-                false
-            }
-            Key::Home => {
-                for cursor in &mut self.cursors {
-                    cursor.apply(&self.rope, Movement::Begin);
+        }
+        match self.layout {
+            Layout::Stacked => {
+                let mut top = 0;
+                for pane in &mut self.panes {
+                    let rows = pane.size;
+                    pane.top = top;
+                    pane.left = 0;
+                    pane.height = rows.saturating_sub(status).max(1);
+                    pane.width = self.width;
+                    pane.bare = self.zen;
+                    top += rows;
                 }
-                false
             }
-            Key::End => {
-                for cursor in &mut self.cursors {
-                    cursor.apply(&self.rope, Movement::End);
+            Layout::SideBySide => {
+                let mut left = 0;
+                for pane in &mut self.panes {
+                    let width = pane.size;
+                    pane.top = 0;
+                    pane.left = left;
+                    pane.height = self.height.saturating_sub(status).max(1);
+                    pane.width = width.max(1);
+                    pane.bare = self.zen;
+                    left += width + 1;
                 }
-                false
             }
-            Key::PageUp => {
-                for cursor in &mut self.cursors {
-                    cursor.apply(&self.rope, Movement::Up(height));
-                }
-                false
+        }
+    }
+
+    // Rows or columns shared between the panes, those of the dividers left out
+    fn room(&self) -> usize {
+        match self.layout {
+            Layout::Stacked => self.height,
+            Layout::SideBySide => self.width.saturating_sub(self.panes.len() - 1),
+        }
+    }
+
+    // Grows the focused pane by rows or columns taken from the next one, or from the previous
+    // one for the last pane, shrinking it when negative. Panes keep a row of text and their
+    // status row, or a column. Returns false if the panes could not be resized.
+    fn resize(&mut self, editors: &[Editor], delta: isize) -> bool {
+        if self.panes.len() < 2 {
+            return false;
+        }
+        let minimum = if self.layout == Layout::Stacked { 2 } else { 1 };
+        let other = if self.focus + 1 < self.panes.len() { self.focus + 1 } else { self.focus - 1 };
+        let (from, to) = if delta > 0 { (other, self.focus) } else { (self.focus, other) };
+        let moved = min(delta.unsigned_abs(), self.panes[from].size.saturating_sub(minimum));
+        if moved == 0 {
+            return false;
+        }
+        self.panes[from].size -= moved;
+        self.panes[to].size += moved;
+        self.layout();
+        // Panes made smaller scroll to keep their cursor in view
+        for pane in &mut self.panes {
+            pane.follow(&editors[pane.buffer]);
+        }
+        true
+    }
+
+    fn pane(&self) -> &Pane {
+        &self.panes[self.focus]
+    }
+
+    fn pane_mut(&mut self) -> &mut Pane {
+        &mut self.panes[self.focus]
+    }
+
+    // Height of the focused pane
+    fn page(&self) -> usize {
+        self.pane().height
+    }
+
+    // Splits the focused pane in two over the same buffer, returns false if there is no room left.
+    // Panes are all laid out the same way, so splitting the other way lays out every pane again.
+    fn split(&mut self, layout: Layout) -> bool {
+        let count = self.panes.len() + 1;
+        let room = match layout {
+            Layout::Stacked => self.height / count >= 2,
+            Layout::SideBySide => self.width.saturating_sub(count - 1) / count >= 1,
+        };
+        if !room {
+            return false;
+        }
+        // Sizes are those of the other direction after splitting the other way
+        if layout != self.layout {
+            for pane in &mut self.panes {
+                pane.size = 0;
             }
-            Key::PageDown => {
-                for cursor in &mut self.cursors {
-                    cursor.apply(&self.rope, Movement::Down(height));
+        }
+        self.layout = layout;
+        let pane = self.pane();
+        let copy = Pane { buffer: pane.buffer, x: pane.x, y: pane.y, top: 0, left: 0, height: 0, width: 0, bare: false, size: 0 };
+        self.panes.insert(self.focus + 1, copy);
+        self.layout();
+        true
+    }
+
+    // Closes the focused pane unless it is the last one, returns the buffer now focused
+    fn close(&mut self) -> usize {
+        if self.panes.len() > 1 {
+            self.panes.remove(self.focus);
+            self.focus = min(self.focus, self.panes.len() - 1);
+            self.layout();
+        }
+        self.pane().buffer
+    }
+
+    // Moves the focus to the next pane, returns the buffer now focused
+    fn cycle(&mut self) -> usize {
+        self.focus = (self.focus + 1) % self.panes.len();
+        self.pane().buffer
+    }
+
+    // Saves the viewport of the current buffer and restores the one of the next in the focused pane
+    fn switch(&mut self, editors: &mut [Editor], current: usize, next: usize) -> usize {
+        let pane = self.pane_mut();
+        editors[current].scroll = (pane.x, pane.y);
+        let (x, y) = editors[next].scroll;
+        pane.x = x;
+        pane.y = y;
+        pane.buffer = next;
+        next
+    }
+
+    // Scrolls the focused pane by a number of lines, the cursor is followed when drawn
+    fn scroll(&mut self, lines: isize) {
+        let pane = self.pane_mut();
+        pane.y = if lines < 0 { pane.y.saturating_sub(lines.unsigned_abs()) } else { pane.y + lines as usize };
+    }
+
+    // Scrolls the focused pane so that the primary cursor is in the middle of it
+    fn center(&mut self, editor: &Editor) {
+        let pane = self.pane_mut();
+        pane.y = match &editor.aligned {
+            Some((rows, _)) => rows[editor.line()].saturating_sub(pane.height / 2),
+            None => editor.above(editor.line(), pane.height / 2),
+        };
+    }
+
+    // Focuses the pane under a mouse press, returns the line and column of the buffer
+    // under it, if it landed on the text of the pane. Dragging stays within the focused pane.
+    fn mouse(&mut self, editors: &[Editor], mouse: MouseEvent) -> Option<(usize, usize)> {
+        self.clamp(editors);
+        match mouse {
+            MouseEvent::Press(button, x, y) => {
+                let (x, y) = (x as usize - 1, y as usize - 1);
+                let focus = self.panes.iter().position(|pane| {
+                    pane.left <= x && x < pane.left + pane.width && pane.top <= y && y < pane.top + pane.height
+                })?;
+                self.focus = focus;
+                let pane = self.pane_mut();
+                let editor = &editors[pane.buffer];
+                // A press on the overview goes to the start of the lines under it, in the middle of the view
+                if pane.minimap(editor) > 0 && x == pane.left + pane.width - 1 && button == MouseButton::Left {
+                    let (line, _) = pane.summed(editor, y - pane.top);
+                    if line >= editor.rope.len_lines() {
+                        return None;
+                    }
+                    let line = editor.shown(line);
+                    pane.y = editor.above(line, pane.height / 2);
+                    return Some((line, 0));
                 }
-                false
+                let line = pane.line_at(editor, y - pane.top);
+                Some((line, pane.column(editor, line, (x - pane.left).saturating_sub(pane.gutter(editor)))))
             }
-            Key::Ctrl('a') => {
-                for cursor in &mut self.cursors {
-                    cursor.apply(&self.rope, Movement::LineBegin);
-                }
-                false
+            MouseEvent::Hold(x, y) => {
+                let pane = self.pane();
+                let editor = &editors[pane.buffer];
+                let (x, y) = pane.cell(editor, x, y);
+                let line = pane.line_at(editor, y);
+                Some((line, pane.column(editor, line, x)))
             }
-            Key::Ctrl('e') => {
-                for cursor in &mut self.cursors {
-                    cursor.apply(&self.rope, Movement::LineEnd);
+            MouseEvent::Release(..) => None,
+        }
+    }
+
+    // Lines from `from` to the one under a drag within the focused pane, each with its column
+    // under the drag
+    fn spanned(&self, editors: &[Editor], from: usize, x: u16, y: u16) -> Vec<(usize, usize)> {
+        let pane = self.pane();
+        let editor = &editors[pane.buffer];
+        let (x, y) = pane.cell(editor, x, y);
+        let to = pane.line_at(editor, y);
+        (min(from, to)..=max(from, to)).map(|line| (line, pane.column(editor, line, x))).collect()
+    }
+
+    // Keeps the view of every pane on its buffer
+    fn clamp(&mut self, editors: &[Editor]) {
+        for pane in &mut self.panes {
+            pane.clamp(&editors[pane.buffer]);
+        }
+    }
+
+    // Stands for all a frame is drawn from: the panes, the buffers they show and the prompt
+    fn appearance(&self, editors: &[Editor], prompt: Option<&Prompt>) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        (self.focus, self.active, self.zen, self.layout == Layout::Stacked).hash(&mut hasher);
+        for pane in &self.panes {
+            (pane.buffer, pane.x, pane.y, pane.top, pane.left, pane.height, pane.width, pane.bare).hash(&mut hasher);
+            editors[pane.buffer].appearance().hash(&mut hasher);
+        }
+        prompt.map(|prompt| (&prompt.label, &prompt.input)).hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn update<S>(&mut self, editors: &[Editor], prompt: Option<&Prompt>, screen: &mut S, draw: bool)
+    where
+        S: Write,
+    {
+        let focus = self.focus;
+        let buffer = self.pane().buffer;
+        self.clamp(editors);
+        let mut need_update = self.pane_mut().follow(&editors[buffer]);
+
+        // Buffers compared side by side scroll together
+        let (x, y) = (self.pane().x, self.pane().y);
+        if editors[buffer].aligned.is_some() {
+            for pane in &mut self.panes {
+                if editors[pane.buffer].aligned.is_some() && (pane.x, pane.y) != (x, y) {
+                    (pane.x, pane.y) = (x, y);
+                    need_update = true;
                 }
-                false
             }
-            Key::Char(c) => {
-                for cursor in &mut self.cursors {
-                    self.rope.insert_char(cursor.pos(&self.rope), c);
-                    cursor.apply(&self.rope, Movement::Right(1));
-                }
-                true
-            }
-            Key::Backspace => {
-                for cursor in &mut self.cursors {
-                    if cursor.pos(&self.rope) > 0 {
-                        cursor.apply(&self.rope, Movement::Left(1));
-                        let pos = cursor.pos(&self.rope);
-                        self.rope.remove(pos..pos + 1);
-                    }
+        }
+
+        // The shape tells the mode, it is only sent when the mode changes
+        let shape = if prompt.is_some() { Shape::Underline } else if editors[buffer].overwrite { Shape::Block } else { Shape::Bar };
+        if shape != self.shape {
+            self.shape = shape;
+            write!(screen, "\x1b[{} q", shape as u8).unwrap();
+            screen.flush().unwrap();
+        }
+
+        // Keys that change nothing, and timers that expire with nothing to show, draw no frame
+        let appearance = self.appearance(editors, prompt);
+        if (draw || need_update) && self.drawn != Some(appearance) {
+            self.drawn = Some(appearance);
+            // Of the frame, only the rows that differ from the last one are written, so that
+            // moving the cursor redraws the rows it leaves and enters and the status row
+            let mut frame = Vec::with_capacity(self.height + self.panes.len());
+            for (i, pane) in self.panes.iter().enumerate() {
+                let editor = &editors[pane.buffer];
+                let mut rows = pane.draw(editor, self.color, self.active);
+                let mut status = Vec::new();
+                pane.status(&mut status, editor, prompt, i == focus);
+                match rows.last_mut() {
+                    Some(last) if pane.bare => last.append(&mut status),
+                    _ => rows.push(status),
                 }
-                true
-            }
-            Key::Delete => {
-                for cursor in &mut self.cursors {
-                    if cursor.pos(&self.rope) < end(&self.rope) {
-                        cursor.apply(&self.rope, Movement::GotoCol(cursor.col(&self.rope)));
-                        let pos = cursor.pos(&self.rope);
-                        self.rope.remove(pos..pos + 1);
+                frame.append(&mut rows);
+                if self.layout == Layout::SideBySide && i + 1 < self.panes.len() {
+                    let mut divider = Vec::new();
+                    for row in 0..self.height {
+                        write!(divider, "{}│", cursor::Goto((pane.left + pane.width) as u16 + 1, row as u16 + 1)).unwrap();
                     }
+                    frame.push(divider);
                 }
-                true
             }
-            Key::Alt('j') => {
-                if let Some(cursor) = self.cursors.first() {
-                    if cursor.line > 0 {
-                        self.cursors.insert(0, Cursor::new(cursor.line - 1, cursor.col));
-                    }
-                }
-                true
+
+            let mut buffer = Vec::with_capacity(self.width * self.height * 2);
+            if frame.len() != self.frame.len() {
+                write!(buffer, "{}{}", style::Reset, clear::All).unwrap();
+                self.frame.clear();
             }
-            Key::Alt('k') => {
-                if let Some(cursor) = self.cursors.last() {
-                    if cursor.line < lines(&self.rope) {
-                        self.cursors.push(Cursor::new(cursor.line + 1, cursor.col));
-                    }
+            for (i, row) in frame.iter().enumerate() {
+                if self.frame.get(i) != Some(row) {
+                    buffer.extend_from_slice(row);
                 }
-                true
             }
-            Key::Esc => {
-                self.cursors.drain(1..);
-                true
-            }
-            _ => { false }
+            self.frame = frame;
+
+            screen.write_all(&buffer).unwrap();
+            screen.flush().unwrap();
         }
     }
+}
 
-    fn mouse(&mut self, mouse: MouseEvent, x: usize, y: usize) {
-        match mouse {
-            MouseEvent::Press(MouseButton::Left, mouse_x, mouse_y) => {
-                if let Some(cursor) = self.cursors.first_mut() {
-                    cursor.apply(&self.rope,
-                        Movement::Goto(y + (mouse_y - 1) as usize, x + (mouse_x - 1) as usize));
-                }
-            },
-            _ => {}
-        }
+// Commands read from the configuration file, run on every new buffer
+struct Config {
+    commands: Vec<Command>,
+}
+
+impl Config {
+    fn path() -> Option<PathBuf> {
+        env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .map(|directory| directory.join("ted").join("tedrc"))
     }
 
-    fn gotoline(&mut self, line: usize) {
-        if let Some(cursor) = self.cursors.first_mut() {
-            cursor.apply(&self.rope, Movement::GotoLine(line));
+    fn load() -> Self {
+        match Self::path().and_then(|path| Some((fs::read_to_string(&path).ok()?, path))) {
+            Some((text, path)) => Self::parse(&text, |n, err| eprintln!("ted: {}:{}: {}", path.display(), n, err)),
+            None => Self { commands: Vec::new() },
         }
     }
 
-    fn save(&self, filename: String) {
-        let mut file = File::create(filename).unwrap();
-        for chunk in self.rope.chunks() {
-            write!(file, "{}", chunk).unwrap();
+    // Reads the commands of the lines of a configuration, calling `error` with the number of
+    // each line that is not one, or is not a command that only sets the buffer up
+    fn parse(text: &str, mut error: impl FnMut(usize, String)) -> Self {
+        let mut commands = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match Command::parse(line) {
+                Ok(command) if command.configures() => commands.push(command),
+                Ok(_) => error(n + 1, format!("Only set, onsave and for can configure: {}", line)),
+                Err(err) => error(n + 1, err),
+            }
         }
-        file.sync_all().unwrap();
+        Self { commands }
     }
 
-    fn draw<W>(&self, w: &mut W, prefix: &str, line: RopeSlice, index: usize)
-    where
-        W: Write,
-    {
-        let mut cursors = self.cursors
-            .iter().filter(|c| c.line == index)
-            .map(|c| min(c.col, columns(line))).collect::<Vec<usize>>();
-
-        cursors.sort();
-
-        write!(w, "{}", prefix).unwrap();
-        let last = cursors.into_iter().fold(0, |last, column| {
-            write!(w, "{}{}{}{}", line.slice(last..column), style::Invert,
-                   if column < line.len_chars() { line.char(column) } else { ' ' },
-                   style::Reset).unwrap();
-            column + 1
-        });
-        if last < line.len_chars() {
-            write!(w, "{}", line.slice(last..)).unwrap();
+    // The `.editorconfig` of the file takes precedence over the configuration, and the
+    // indentation found in the file over both unless the `.editorconfig` sets it
+    fn apply(&self, mut editor: Editor) -> Editor {
+        for command in &self.commands {
+            editor.command(command);
+        }
+        if !editor.configure() {
+            editor.detect_indentation();
         }
+        editor
     }
 }
 
-struct TermRenderer {
-    pub y: usize,
-    pub x: usize,
-    height: usize,
-    width: usize,
-}
+// Switches to the buffer of a file, opening it if it is not already
+fn open(editors: &mut Vec<Editor>, renderer: &mut TermRenderer, config: &Config, current: usize, path: &Path) -> usize {
+    let canonical = fs::canonicalize(path).ok();
+    let existing = editors.iter().position(|editor| {
+        editor.filename.as_ref().map(fs::canonicalize).and_then(Result::ok) == canonical
+    });
 
-impl TermRenderer {
-    fn new() -> Self {
-        let (width, height) = terminal_size().unwrap();
-        Self {
-            x: 0,
-            y: 0,
-            height: height as usize,
-            width: width as usize,
+    let next = match existing {
+        Some(next) if canonical.is_some() => next,
+        _ => {
+            editors.push(config.apply(Editor::open(path.to_string_lossy().into_owned())));
+            editors.len() - 1
         }
-    }
+    };
+    renderer.switch(editors, current, next)
+}
 
-    fn update<S>(&mut self, editor: &Editor, screen: &mut S, draw: bool)
-    where
-        S: Write,
-    {
-        let mut need_update = true;
-        if editor.line() < self.y {
-            self.y = editor.line();
-            need_update = true;
+// Opens the file of the current buffer again as saved, read-only in a buffer of its own with
+// the cursor where it is in the current one, returns the buffer now shown
+fn view(editors: &mut Vec<Editor>, renderer: &mut TermRenderer, config: &Config, current: usize) -> usize {
+    let filename = match editors[current].filename.clone() {
+        Some(filename) if Path::new(&filename).is_file() => filename,
+        Some(filename) => {
+            editors[current].set_message(format!("\"{}\" is not saved yet", filename));
+            return current;
         }
-
-        if editor.line() >= self.y + self.height {
-            self.y = editor.line() - self.height + 1;
-            need_update = true;
+        None => {
+            editors[current].set_message("No file to view");
+            return current;
         }
+    };
+    // Read like the file was, in the encoding found for it unless that was UTF-8
+    let encoding = Some(editors[current].encoding).filter(|&encoding| encoding != Encoding::Utf8);
+    let mut editor = config.apply(Editor::open_as(filename, encoding));
+    editor.readonly = true;
+    editor.set_cursor(editors[current].line(), editors[current].col());
+    editors.push(editor);
+    let next = editors.len() - 1;
+    renderer.switch(editors, current, next)
+}
 
-        if editor.col() < self.x {
-            self.x = editor.col();
-            need_update = true;
+// Shows the line typed in the go-to prompt, or goes back to where the prompt was opened
+// when it is not a line. Returns whether it was a line.
+fn preview(editor: &mut Editor, renderer: &mut TermRenderer, input: &str, origin: (usize, usize, usize)) -> bool {
+    match Command::parse(input) {
+        Ok(command @ Command::Goto(_)) | Ok(command @ Command::Percent(_)) | Ok(command @ Command::Byte(_)) => {
+            editor.command(&command);
+            renderer.center(editor);
+            true
         }
-
-        if editor.col() >= self.x + self.width {
-            self.x = editor.col() - self.width + 1;
-            need_update = true;
+        _ => {
+            let (line, col, top) = origin;
+            editor.set_cursor(line, col);
+            renderer.pane_mut().y = top;
+            false
         }
+    }
+}
 
-        if draw || need_update {
+// Plays the macro of a buffer back through the event loop, as many times as the count
+// typed before it, for the keys handled there to be played back too
+fn play(editor: &mut Editor, played: &mut VecDeque<Key>) {
+    for _ in 0..editor.count.take().unwrap_or(1) {
+        played.extend(editor.recorded.iter().copied());
+    }
+}
 
-            let mut buffer = Vec::with_capacity(self.width * self.height * 2);
-            write!(buffer, "{}", clear::All).unwrap();
-            write!(buffer, "{}", cursor::Goto(1, 1)).unwrap();
-            write!(buffer, "{}", style::Reset).unwrap();
-
-            let mut lines = editor
-                .rope
-                .lines()
-                .map(|l| {
-                    let max = columns(l);
-                    l.slice(min(self.x, max)..min(self.x + self.width, max))
-                })
-                .skip(self.y)
-                .take(self.height as usize);
-
-            let mut ln = self.y;
-            if let Some(first) = lines.next() {
-                editor.draw(&mut buffer, "\r", first, ln);
-                ln += 1;
-                for line in lines {
-                    editor.draw(&mut buffer, "\n\r", line, ln);
-                    ln += 1;
-                }
+// Runs a command on the current buffer, returns true if the editor should quit
+fn run(editors: &mut [Editor], current: usize, command: &Command) -> bool {
+    // A buffer written to another file is left changed, which doesn't keep the editor from quitting
+    let written_as = match command {
+        Command::Write { filename: Some(filename), quit: true } if editors[current].other_file(filename) => {
+            if !editors[current].write_as(filename) {
+                return false;
             }
+            true
+        }
+        _ => {
+            editors[current].command(command);
+            false
+        }
+    };
+    let force = match command {
+        _ if written_as => false,
+        Command::Write { quit: true, .. } if editors[current].finish() => false,
+        Command::Quit { force } => *force,
+        _ => return false,
+    };
 
-            screen.write(&buffer).unwrap();
-            screen.flush().unwrap();
+    if !force {
+        let dirty = editors.iter().enumerate().find(|&(i, editor)| editor.dirty && !(written_as && i == current));
+        if let Some((_, editor)) = dirty {
+            let message = format!("No write since last change for \"{}\" (add ! to override)", editor.name());
+            editors[current].set_message(message);
+            return false;
         }
     }
+    true
 }
 
 fn main() {
     let result = panic::catch_unwind(|| {
-        let rope = if let Some(path) = args().nth(1) {
-            if let Ok(file) = File::open(path) {
-                Rope::from_reader(file).unwrap()
-            } else {
-                Rope::new()
+        let mut path = None;
+        let mut commands = Vec::new();
+        let mut color = true;
+        let mut language = None;
+        let mut encoding = None;
+        let mut alternate = true;
+        let mut diff = None;
+        let mut standard = false;
+        let mut arguments = args().skip(1);
+        while let Some(arg) = arguments.next() {
+            match arg.as_str() {
+                "-c" => match arguments.next() {
+                    Some(command) => commands.push(command),
+                    None => eprintln!("ted: -c requires a command"),
+                },
+                "-t" => match arguments.next() {
+                    Some(extension) => language = Some(extension.trim_start_matches('.').to_string()),
+                    None => eprintln!("ted: -t requires an extension"),
+                },
+                "--stdin-filename" => match arguments.next() {
+                    Some(name) => {
+                        language = Path::new(&name).extension().map(|extension| extension.to_string_lossy().into_owned());
+                    }
+                    None => eprintln!("ted: --stdin-filename requires a file name"),
+                },
+                "--encoding" => match arguments.next().map(|name| (Encoding::from_name(&name), name)) {
+                    Some((Some(found), _)) => encoding = Some(found),
+                    Some((None, name)) => eprintln!("ted: unknown encoding: {}", name),
+                    None => eprintln!("ted: --encoding requires an encoding"),
+                },
+                "--no-color" => color = false,
+                "--no-alt-screen" => alternate = false,
+                "--diff" => match (arguments.next(), arguments.next()) {
+                    (Some(first), Some(second)) => diff = Some((first, second)),
+                    _ => {
+                        eprintln!("ted: --diff requires two files");
+                        return;
+                    }
+                },
+                "-V" | "--version" => {
+                    println!("ted {}", env!("CARGO_PKG_VERSION"));
+                    return;
+                }
+                "-h" | "--help" => {
+                    print!("{}", HELP);
+                    return;
+                }
+                // The text is read from the standard input, also from a terminal until its end
+                "-" => standard = true,
+                _ => path = Some(arg),
+            }
+        }
+
+        // Keys are read from the terminal when the text comes from a pipe
+        let piped = standard || !is_tty(&stdin());
+        let mut editor = if let Some(path) = path.filter(|_| !standard) {
+            Editor::open_as(path, encoding)
+        } else if piped {
+            let mut bytes = Vec::new();
+            if let Err(err) = stdin().read_to_end(&mut bytes) {
+                eprintln!("ted: cannot read the standard input: {}", err);
+                return;
+            }
+            let encoding = encoding.unwrap_or_else(|| Encoding::detect(&bytes));
+            let mut editor = Editor::new(Rope::from_str(&encoding.decode(&bytes).0));
+            editor.encoding = encoding;
+            editor
+        } else {
+            Editor::new(Rope::new())
+        };
+        editor.language = language;
+        let input: Box<dyn Read + Send> = if piped {
+            match get_tty() {
+                Ok(tty) => Box::new(tty),
+                Err(err) => {
+                    eprintln!("ted: cannot open the terminal: {}", err);
+                    return;
+                }
             }
         } else {
-            Rope::new()
+            Box::new(stdin())
+        };
+        let config = Config::load();
+        let mut history = History::load();
+        let editor = config.apply(editor);
+
+        // Files compared are each in a buffer, the first one on the left
+        let mut editors = match &diff {
+            Some((first, second)) => {
+                let mut first = config.apply(Editor::open_as(first.clone(), encoding));
+                let mut second = config.apply(Editor::open_as(second.clone(), encoding));
+                let rows = compare(&first.rope, &second.rope);
+                first.compared(&rows, true);
+                second.compared(&rows, false);
+                vec![first, second]
+            }
+            None => vec![editor],
         };
 
-        let mut editor = Editor::new(rope);
+        // Run the commands given with -c once the buffer is loaded
+        for command in commands {
+            match Command::parse(&command) {
+                Ok(command) => if run(&mut editors, 0, &command) { return },
+                Err(err) => {
+                    eprintln!("ted: {}", err);
+                    editors[0].set_message(err);
+                }
+            }
+        }
+
+        let mut current = 0;
+        let mut prompt: Option<Prompt> = None;
 
-        let mut renderer = TermRenderer::new();
+        // https://no-color.org
+        let color = color && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty());
+        let mut renderer = TermRenderer::new(color);
+        if diff.is_some() {
+            if renderer.split(Layout::SideBySide) {
+                renderer.panes[1].buffer = 1;
+            } else {
+                editors[0].set_message("Not enough room to compare side by side");
+            }
+        }
 
         let raw = stdout().into_raw_mode().unwrap();
 
-        let stdin = stdin();
-        let screen = stdout();
-        let screen = screen::AlternateScreen::from(screen);
+        let screen: Box<dyn Write> = if alternate { Box::new(screen::AlternateScreen::from(stdout())) } else { Box::new(stdout()) };
         let screen = cursor::HideCursor::from(screen);
         let mut screen = MouseTerminal::from(screen);
 
+        // Have the terminal report when it gains and loses the focus
+        write!(screen, "\x1b[?1004h").unwrap();
+
         // Switch to Main screen and disable raw mode before panic
         let panic_hook = panic::take_hook();
         panic::set_hook(Box::new(move |info| {
-            eprint!("{}", screen::ToMainScreen);
+            if alternate {
+                eprint!("{}", screen::ToMainScreen);
+            }
+            eprint!("\x1b[{} q\x1b[?1004l", Shape::Default as u8);
             let _ = raw.suspend_raw_mode();
             panic_hook(info);
             let _ = raw.activate_raw_mode();
-            eprint!("{}", screen::ToAlternateScreen);
+            if alternate {
+                eprint!("{}", screen::ToAlternateScreen);
+            }
         }));
 
-        // Cursor shape, https://invisible-island.net/xterm/ctlseqs/ctlseqs.html
-        write!(screen, "\x1b[6 q").unwrap();
+        renderer.center(&editors[current]);
+        renderer.update(&editors, None, &mut screen, true);
 
-        renderer.update(&editor, &mut screen, true);
+        // Read events on their own thread so the loop can wake up on timeouts
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            for evt in input.events() {
+                if sender.send(evt).is_err() { break }
+            }
+        });
 
-        for c in stdin.events() {
-            let evt = c.unwrap();
-            let draw = match evt {
-                Event::Key(Key::Ctrl('q')) => break,
-                Event::Key(Key::Ctrl('s')) => { if let Some(path) = args().nth(1) { editor.save(path); } false },
-                Event::Key(key) => editor.key(key, renderer.height - 1),
-                Event::Mouse(mouse) => { editor.mouse(mouse, renderer.x, renderer.y); false },
-                _ => { false }
-            };
-            renderer.update(&editor, &mut screen, draw);
+        // Line a drag with Alt started on
+        let mut dragged = None;
+        // Keys of the macro being played back
+        let mut played = VecDeque::new();
+
+        'events: loop {
+            // Buffers in the background keep saving and comparing with git
+            let evt = if let Some(timeout) = editors.iter().filter_map(Editor::timeout).min() {
+                match receiver.recv_timeout(timeout) {
+                    Ok(evt) => evt,
+                    Err(RecvTimeoutError::Timeout) => {
+                        if editors.iter_mut().fold(false, |expired, editor| editor.expire() | expired) {
+                            renderer.update(&editors, prompt.as_ref(), &mut screen, true);
+                        }
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            } else if let Ok(evt) = receiver.recv() { evt } else { break };
+
+            // Handle the events already read before drawing, so that a burst of keys,
+            // like text pasted without bracketed paste, is drawn only once
+            let mut draw = false;
+            let mut next = Some(evt);
+            loop {
+                // The keys of a macro played back go before the events read after it. The keys
+                // read are recorded as they come, those played back not again.
+                let (input, playing) = if let Some(key) = played.pop_front() {
+                    (Input::Key(key), true)
+                } else if let Some(evt) = next.take().or_else(|| receiver.try_recv().ok()) {
+                    // A failed read is not worth losing the buffers over, wait for the next event
+                    let evt = if let Ok(evt) = evt { evt } else { continue };
+                    (Input::from(evt), false)
+                } else {
+                    break;
+                };
+
+                let mut cleared = false;
+                if let Input::Key(key) = input {
+                    if !playing {
+                        editors[current].record(key);
+                    }
+                    cleared = editors[current].message.take().is_some();
+                }
+
+                if let Some(mut active) = prompt.take() {
+                    if let Input::Key(key) = input {
+                        match active.key(key, &history) {
+                            Some(true) => match active.action {
+                                Action::SaveAs => {
+                                    if !active.input.is_empty() {
+                                        let editor = &mut editors[current];
+                                        editor.filename = Some(active.input);
+                                        editor.save();
+                                    }
+                                }
+                                Action::Goto { line, col, top } => {
+                                    if !preview(&mut editors[current], &mut renderer, &active.input, (line, col, top)) {
+                                        editors[current].set_message(format!("Invalid line: {}", active.input));
+                                    }
+                                }
+                                Action::Command => {
+                                    history.push(&active.input);
+                                    match Command::parse(&active.input) {
+                                        // Replacing all over the buffer waits for the number of replacements to be confirmed
+                                        Ok(command @ Command::Substitute { all: true, .. }) => {
+                                            let count = match &command {
+                                                Command::Substitute { pattern, global, .. } => editors[current].substitutions(pattern, *global, true),
+                                                _ => 0,
+                                            };
+                                            if count == 0 {
+                                                run(&mut editors, current, &command);
+                                            } else {
+                                                let label = format!("Replace {} occurrence{}? (y/n) ", count, if count == 1 { "" } else { "s" });
+                                                prompt = Some(Prompt::new(&label, Action::Confirm(command)));
+                                            }
+                                        }
+                                        Ok(Command::View) => current = view(&mut editors, &mut renderer, &config, current),
+                                        Ok(command) => if run(&mut editors, current, &command) { break 'events },
+                                        Err(err) => editors[current].set_message(err),
+                                    }
+                                }
+                                Action::Confirm(command) => { run(&mut editors, current, &command); }
+                                Action::Edit(Key::Alt('@')) => play(&mut editors[current], &mut played),
+                                Action::Edit(key) => {
+                                    editors[current].key(key, renderer.page() - 1);
+                                    renderer.scroll(mem::take(&mut editors[current].scrolled));
+                                }
+                                // Inserted as if typed at each cursor
+                                Action::Unicode => match code_point(&active.input) {
+                                    Ok(c) => { editors[current].key(Key::Char(c), renderer.page() - 1); }
+                                    Err(err) => editors[current].set_message(err),
+                                },
+                            },
+                            Some(false) => match active.action {
+                                Action::Goto { line, col, top } => { preview(&mut editors[current], &mut renderer, "", (line, col, top)); }
+                                Action::Confirm(_) => editors[current].set_message("Nothing replaced"),
+                                Action::Edit(_) => editors[current].set_message("Nothing edited"),
+                                _ => {}
+                            },
+                            None => {
+                                if let Action::Goto { line, col, top } = active.action {
+                                    preview(&mut editors[current], &mut renderer, &active.input, (line, col, top));
+                                }
+                                prompt = Some(active);
+                            }
+                        }
+                    } else {
+                        prompt = Some(active);
+                    }
+                    draw = true;
+                    continue;
+                }
+
+                draw |= cleared | match input {
+                    Input::Key(key) => match key {
+                        Key::Ctrl('q') => break 'events,
+                        Key::Ctrl('s') => {
+                            let editor = &mut editors[current];
+                            if editor.filename.is_some() { editor.save(); }
+                            else { prompt = Some(Prompt::new("Save as: ", Action::SaveAs)); }
+                            true
+                        }
+                        Key::Ctrl('g') => {
+                            let action = Action::Goto { line: editors[current].line(), col: editors[current].col(), top: renderer.pane().y };
+                            prompt = Some(Prompt::new("Go to line (N%, byte N): ", action));
+                            true
+                        }
+                        Key::Alt(':') => {
+                            prompt = Some(Prompt::new(":", Action::Command));
+                            true
+                        }
+                        Key::Ctrl('v') => {
+                            prompt = Some(Prompt::new("Insert U+", Action::Unicode));
+                            true
+                        }
+                        // The command prompt, started with the filter command
+                        Key::Alt('|') => {
+                            let mut filter = Prompt::new(":", Action::Command);
+                            filter.input.push('!');
+                            prompt = Some(filter);
+                            true
+                        }
+                        // The command prompt, started with the join command
+                        Key::Alt('J') => {
+                            let mut join = Prompt::new(":", Action::Command);
+                            join.input.push_str("join ");
+                            prompt = Some(join);
+                            true
+                        }
+                        Key::Alt('g') => {
+                            if let Some(path) = editors[current].path_under_cursor() {
+                                if path.exists() {
+                                    current = open(&mut editors, &mut renderer, &config, current, &path);
+                                } else {
+                                    editors[current].set_message(format!("File not found: {}", path.display()));
+                                }
+                            }
+                            true
+                        }
+                        // Enter on a directory listing opens the entry under the cursor,
+                        // a directory replacing the listing so it can be browsed in place
+                        Key::Char('\n') if editors[current].entry().is_some() => {
+                            if let Some(path) = editors[current].entry() {
+                                let path = fs::canonicalize(&path).unwrap_or(path);
+                                if path.is_dir() {
+                                    editors[current] = config.apply(Editor::open(path.to_string_lossy().into_owned()));
+                                    renderer.center(&editors[current]);
+                                } else {
+                                    current = open(&mut editors, &mut renderer, &config, current, &path);
+                                }
+                            }
+                            true
+                        }
+                        Key::Ctrl('n') => {
+                            editors.push(config.apply(Editor::new(Rope::new())));
+                            let last = editors.len() - 1;
+                            current = renderer.switch(&mut editors, current, last);
+                            true
+                        }
+                        Key::Alt('n') => {
+                            let next = (current + 1) % editors.len();
+                            current = renderer.switch(&mut editors, current, next);
+                            true
+                        }
+                        Key::Alt('p') => {
+                            let previous = (current + editors.len() - 1) % editors.len();
+                            current = renderer.switch(&mut editors, current, previous);
+                            true
+                        }
+                        Key::Alt(c @ 's') | Key::Alt(c @ 'v') => {
+                            let layout = if c == 's' { Layout::Stacked } else { Layout::SideBySide };
+                            if !renderer.split(layout) {
+                                editors[current].set_message("Not enough room to split");
+                            }
+                            true
+                        }
+                        Key::Alt('f') => {
+                            renderer.zen = !renderer.zen;
+                            renderer.layout();
+                            true
+                        }
+                        Key::Alt(c @ '=') | Key::Alt(c @ '-') => {
+                            if !renderer.resize(&editors, if c == '=' { 1 } else { -1 }) {
+                                editors[current].set_message("Cannot resize the pane");
+                            }
+                            true
+                        }
+                        Key::Alt('w') => {
+                            current = renderer.cycle();
+                            true
+                        }
+                        Key::Alt('c') => {
+                            current = renderer.close();
+                            true
+                        }
+                        // Keys of a macro are not held up midway, the prompt would take the keys after them
+                        key => match editors[current].guarded(key).filter(|_| !playing) {
+                            // Editing at many cursors at once waits for it to be confirmed
+                            Some(count) => {
+                                prompt = Some(Prompt::new(&format!("Edit with {} cursors? (y/n) ", count), Action::Edit(key)));
+                                true
+                            }
+                            None if key == Key::Alt('@') => {
+                                play(&mut editors[current], &mut played);
+                                false
+                            }
+                            None => {
+                                let draw = editors[current].key(key, renderer.page() - 1);
+                                renderer.scroll(mem::take(&mut editors[current].scrolled));
+                                draw
+                            }
+                        },
+                    },
+                    Input::Mouse(mouse) => match renderer.mouse(&editors, mouse) {
+                        Some((line, col)) => {
+                            current = renderer.pane().buffer;
+                            match mouse {
+                                MouseEvent::Press(MouseButton::Left, ..) => editors[current].set_cursor(line, col),
+                                MouseEvent::Hold(..) => editors[current].select(line, col),
+                                _ => false,
+                            }
+                        }
+                        None => false,
+                    },
+                    Input::Bytes(bytes) if bytes == b"\x1b[I" || bytes == b"\x1b[O" => {
+                        renderer.active = bytes == b"\x1b[I";
+                        true
+                    }
+                    // Dragging with Alt puts a cursor on each line from the one pressed on
+                    Input::Bytes(bytes) => if let Some(mouse) = alt_mouse(&bytes) {
+                        match (mouse, renderer.mouse(&editors, mouse)) {
+                            (MouseEvent::Press(..), Some((line, col))) => {
+                                current = renderer.pane().buffer;
+                                dragged = Some(line);
+                                editors[current].set_cursors(&[(line, col)])
+                            }
+                            (MouseEvent::Hold(x, y), Some(_)) => {
+                                let from = dragged.unwrap_or_else(|| editors[current].line());
+                                let positions = renderer.spanned(&editors, from, x, y);
+                                editors[current].set_cursors(&positions)
+                            }
+                            _ => false,
+                        }
+                    } else {
+                        let editor = &mut editors[current];
+                        if editor.options.show_unmapped {
+                            editor.set_message(format!("Unmapped sequence: {:?}", String::from_utf8_lossy(&bytes)));
+                        }
+                        editor.options.show_unmapped
+                    }
+                };
+            }
+            if mem::take(&mut editors[current].bell) {
+                write!(screen, "\x07").unwrap();
+                screen.flush().unwrap();
+            }
+            renderer.update(&editors, prompt.as_ref(), &mut screen, draw);
+        }
+
+        // Saves still running are waited for, so that they are not cut short
+        for editor in &mut editors {
+            editor.finish();
         }
+
+        // Give the terminal its own cursor back, and stop focus reports
+        write!(screen, "\x1b[{} q\x1b[?1004l", Shape::Default as u8).unwrap();
+        // The shell goes on below the last frame drawn when it is left on its screen
+        if !alternate {
+            let (_, height) = terminal_size().unwrap_or((80, 24));
+            write!(screen, "{}{}\r\n", style::Reset, cursor::Goto(1, height)).unwrap();
+        }
+        screen.flush().unwrap();
+        // The hook holds the raw terminal, which restores the terminal mode once dropped
+        drop(panic::take_hook());
     });
 
     // Always try to flush after unwind to quit alternate screen
@@ -459,3 +1700,395 @@ fn main() {
         panic::resume_unwind(err);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbers(count: usize) -> Editor {
+        Editor::new(Rope::from_str(&(1..=count).map(|n| format!("{}\n", n)).collect::<String>()))
+    }
+
+    #[test]
+    fn written_as_and_quit() {
+        let path = std::env::temp_dir().join(format!("ted-quit-{}", std::process::id())).to_string_lossy().into_owned();
+        let other = format!("{}-other", path);
+        fs::write(&path, "one\n").unwrap();
+        let command = |text: &str| Command::parse(text).unwrap();
+
+        let mut editors = vec![Editor::open(path.clone()), numbers(2)];
+        editors[0].key(Key::Char('x'), 1);
+        editors[1].key(Key::Char('x'), 1);
+        // Another buffer with changes keeps the editor open, once the file is written
+        assert!(!run(&mut editors, 0, &command(&format!("wq {}", other))));
+        assert_eq!(fs::read_to_string(&other).unwrap(), "xone\n");
+        assert!(editors[0].message.as_ref().unwrap().0.starts_with("No write since last change for \"[No Name]\""));
+        editors[1].dirty = false;
+        assert!(run(&mut editors, 0, &command(&format!("wq {}", other))));
+        // The buffer's own file is not written
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one\n");
+        assert!(editors[0].dirty);
+
+        // Nor is the editor quit when the file cannot be written
+        let missing = format!("{}-missing/file", path);
+        assert!(!run(&mut editors, 0, &command(&format!("wq {}", missing))));
+        assert!(editors[0].message.as_ref().unwrap().0.starts_with("Cannot save"));
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&other).unwrap();
+    }
+
+    #[test]
+    fn configuration() {
+        let text = "# comment\nset tabwidth=2\n\nonsave rs rustfmt\nfor md set wrap\ns/a/b/\nfor rs join\nw\nnonsense\n";
+        let mut errors = Vec::new();
+        let config = Config::parse(text, |n, err| errors.push((n, err)));
+        assert_eq!(config.commands.len(), 3);
+        assert_eq!(errors.iter().map(|&(n, _)| n).collect::<Vec<_>>(), vec![6, 7, 8, 9]);
+        assert_eq!(errors[0].1, "Only set, onsave and for can configure: s/a/b/");
+
+        // Nothing but the options of the buffers opened changes
+        let editor = config.apply(Editor::new(Rope::from_str("a\n")));
+        assert_eq!((editor.rope.to_string(), editor.dirty, editor.options.tab_width), ("a\n".to_string(), false, 2));
+    }
+
+    #[test]
+    fn history() {
+        let directory = env::temp_dir().join(format!("ted-history-{}", std::process::id()));
+        let path = directory.join("state").join("history");
+        let mut history = History::at(Some(path.clone()));
+        assert!(history.entries.is_empty());
+        // Saved in directories made for it, without blank entries or a repeat of the last one
+        for entry in ["w", "w", " ", "set tabwidth=2", "w"] {
+            history.push(entry);
+        }
+        assert_eq!(history.entries, ["w", "set tabwidth=2", "w"]);
+        assert_eq!(History::at(Some(path.clone())).entries, history.entries);
+
+        // Up goes back through the entries, Down past the newest one to the input typed
+        let mut prompt = Prompt::new(":", Action::Command);
+        prompt.input.push_str("typed");
+        let mut inputs = Vec::new();
+        for key in [Key::Up, Key::Up, Key::Up, Key::Up, Key::Down, Key::Down, Key::Down, Key::Down] {
+            prompt.key(key, &history);
+            inputs.push(prompt.input.clone());
+        }
+        assert_eq!(inputs, ["w", "set tabwidth=2", "w", "w", "set tabwidth=2", "w", "typed", "typed"]);
+
+        // As many as the limit are kept, the oldest going first, and read back
+        for n in 0..HISTORY_SIZE + 5 {
+            history.push(&n.to_string());
+        }
+        assert_eq!((history.entries.len(), history.entries[0].as_str()), (HISTORY_SIZE, "5"));
+        fs::write(&path, (0..HISTORY_SIZE + 5).map(|n| format!("{}\n", n)).collect::<String>()).unwrap();
+        assert_eq!(History::at(Some(path.clone())).entries[0], "5");
+
+        // A file that cannot be read or written leaves the history to the session
+        fs::remove_dir_all(&directory).unwrap();
+        fs::write(&directory, "").unwrap();
+        let mut history = History::at(Some(path));
+        history.push("q");
+        assert_eq!(history.entries, ["q"]);
+        fs::remove_file(&directory).unwrap();
+        let mut history = History::at(None);
+        history.push("q");
+        assert_eq!(history.entries, ["q"]);
+    }
+
+    #[test]
+    fn modified_keys() {
+        for (bytes, key) in [
+            (&b"\x1b[1;2A"[..], Some(Key::Shift(Nav::Up))),
+            (b"\x1b[1;3B", Some(Key::AltNav(Nav::Down))),
+            (b"\x1b[1;4C", Some(Key::AltShift(Nav::Right))),
+            (b"\x1b[1;5D", Some(Key::CtrlNav(Nav::Left))),
+            (b"\x1b[1;6H", Some(Key::CtrlShift(Nav::Home))),
+            (b"\x1b[1;5F", Some(Key::CtrlNav(Nav::End))),
+            (b"\x1b[5;5~", Some(Key::CtrlNav(Nav::PageUp))),
+            (b"\x1b[6;2~", Some(Key::Shift(Nav::PageDown))),
+            (b"\x1b[7;5~", Some(Key::CtrlNav(Nav::Home))),
+            (b"\x1b[8;3~", Some(Key::AltNav(Nav::End))),
+            // rxvt
+            (b"\x1b[a", Some(Key::Shift(Nav::Up))),
+            (b"\x1b[d", Some(Key::Shift(Nav::Left))),
+            (b"\x1bOc", Some(Key::CtrlNav(Nav::Right))),
+            (b"\x1bOb", Some(Key::CtrlNav(Nav::Down))),
+            // Unknown sequences and modifiers
+            (b"\x1b[1;7A", None),
+            (b"\x1b[1;5Z", None),
+            (b"\x1b[9;5~", None),
+            (b"\x1b[1A", None),
+            (b"\x1b[e", None),
+            (b"\x1bOP", None),
+            (b"\x1b[", None),
+            (b"\x1b[1;\xff~", None),
+            (b"a", None),
+        ] {
+            assert_eq!(modified(bytes), key, "{:?}", bytes);
+        }
+    }
+
+    #[test]
+    fn macros_played_through_the_loop() {
+        assert!(matches!(Input::from(Event::Unsupported(b"\x1b[1;2A".to_vec())), Input::Key(Key::Shift(Nav::Up))));
+        assert!(matches!(Input::from(Event::Key(event::Key::Alt('J'))), Input::Key(Key::Alt('J'))));
+        assert!(matches!(Input::from(Event::Unsupported(b"\x1b[I".to_vec())), Input::Bytes(_)));
+
+        let mut editor = numbers(1);
+        editor.recorded = vec![Key::Alt('J'), Key::Char('\n')];
+        let mut played = VecDeque::new();
+        play(&mut editor, &mut played);
+        assert_eq!(played, [Key::Alt('J'), Key::Char('\n')]);
+        played.clear();
+        editor.key(Key::Alt('3'), 1);
+        play(&mut editor, &mut played);
+        assert_eq!((played.len(), editor.count), (6, None));
+    }
+
+    #[test]
+    fn keys_changing_nothing_write_nothing() {
+        let mut editors = vec![numbers(20)];
+        let mut renderer = TermRenderer::sized(false, 40, 10);
+        let mut screen = Vec::new();
+        renderer.update(&editors, None, &mut screen, true);
+        assert!(!screen.is_empty());
+        for key in [Key::Null, Key::Up, Key::Left, Key::Alt('x')] {
+            let draw = editors[0].key(key, 8);
+            let mut screen = Vec::new();
+            renderer.update(&editors, None, &mut screen, draw);
+            assert!(screen.is_empty(), "{:?}", key);
+        }
+        editors[0].key(Key::Down, 8);
+        let mut screen = Vec::new();
+        renderer.update(&editors, None, &mut screen, true);
+        assert!(!screen.is_empty());
+    }
+
+    // Cells a row is drawn in, each with whether it is inverted, the second cell of a wide
+    // character being a NUL
+    fn screen(row: &[u8]) -> Vec<(char, bool)> {
+        let row = String::from_utf8_lossy(row);
+        let mut chars = row.chars().peekable();
+        let (mut cells, mut inverted) = (Vec::new(), false);
+        while let Some(c) = chars.next() {
+            if c != '\x1b' {
+                cells.push((c, inverted));
+                if ted::width(c) == 2 {
+                    cells.push(('\0', inverted));
+                }
+                continue;
+            }
+            chars.next();
+            let mut params = String::new();
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    if c == 'm' {
+                        for param in params.split(';') {
+                            match param {
+                                "" | "0" => inverted = false,
+                                "7" => inverted = true,
+                                _ => {}
+                            }
+                        }
+                    }
+                    break;
+                }
+                params.push(c);
+            }
+        }
+        cells
+    }
+
+    fn inverted(row: &[u8]) -> Vec<usize> {
+        screen(row).iter().enumerate().filter(|(_, &(_, inverted))| inverted).map(|(i, _)| i).collect()
+    }
+
+    fn pane() -> Pane {
+        Pane { buffer: 0, x: 0, y: 0, top: 0, left: 0, height: 3, width: 20, bare: true, size: 3 }
+    }
+
+    #[test]
+    fn cursors_on_wide_characters() {
+        let mut editor = Editor::new(Rope::from_str("\u{3042}\u{3044}\n"));
+        let rows = pane().draw(&editor, false, true);
+        assert_eq!(inverted(&rows[0]), vec![0, 1]);
+        assert_eq!(screen(&rows[0])[2..4], [('\u{3044}', false), ('\0', false)]);
+        editor.set_cursor(0, 1);
+        assert_eq!(inverted(&pane().draw(&editor, false, true)[0]), vec![2, 3]);
+        editor.set_cursor(0, 2);
+        assert_eq!(inverted(&pane().draw(&editor, false, true)[0]), vec![4]);
+    }
+
+    #[test]
+    fn cursors_over_tabs() {
+        let mut editor = Editor::new(Rope::from_str("a\tb\n    c\n"));
+        editor.options.tab_width = 4;
+        // On the tab, over the cells up to the tab stop, then on the character after it
+        editor.set_cursor(0, 1);
+        assert_eq!(inverted(&pane().draw(&editor, false, true)[0]), vec![1, 2, 3]);
+        editor.set_cursor(0, 2);
+        assert_eq!(inverted(&pane().draw(&editor, false, true)[0]), vec![4]);
+        // The same cell as with spaces
+        editor.set_cursor(1, 4);
+        assert_eq!(inverted(&pane().draw(&editor, false, true)[1]), vec![4]);
+
+        // Tab stops are counted from the start of the line, with the view scrolled too
+        let mut pane = pane();
+        pane.x = 1;
+        editor.set_cursor(0, 2);
+        assert_eq!(inverted(&pane.draw(&editor, false, true)[0]), vec![3]);
+    }
+
+    #[test]
+    fn indent_guides_over_tabs() {
+        let mut editor = Editor::new(Rope::from_str("\t\tx\n        x\n \t  x\n"));
+        editor.options.tab_width = 4;
+        editor.options.indent_guides = true;
+        editor.set_cursor(2, 5);
+        let rows = pane().draw(&editor, false, true);
+        let text = |row: &[u8]| screen(row).iter().map(|&(c, _)| c).collect::<String>();
+        // At the same cells whether the indentation is of tabs, spaces or both
+        assert_eq!(text(&rows[0]).trim_end(), "│   │   x");
+        assert_eq!(text(&rows[1]).trim_end(), "│   │   x");
+        assert_eq!(text(&rows[2]).trim_end(), "│   │ x");
+
+        // Counted from the start of the line when it is scrolled
+        let mut pane = pane();
+        pane.x = 1;
+        assert_eq!(text(&pane.draw(&editor, false, true)[0]).trim_end(), "│   x");
+    }
+
+    #[test]
+    fn cursors_past_the_end_of_lines() {
+        // On an empty line, at the end of one, and at the end of the last one without a line break
+        let mut editor = Editor::new(Rope::from_str("\nab\nab"));
+        let rows = pane().draw(&editor, false, true);
+        assert_eq!(inverted(&rows[0]), vec![0]);
+        assert_eq!(inverted(&rows[1]), Vec::<usize>::new());
+        editor.set_cursor(1, 2);
+        let rows = pane().draw(&editor, false, true);
+        assert_eq!(inverted(&rows[1]), vec![2]);
+        assert_eq!(screen(&rows[1])[..2], [('a', false), ('b', false)]);
+        editor.set_cursor(2, 2);
+        let rows = pane().draw(&editor, false, true);
+        assert_eq!(inverted(&rows[2]), vec![2]);
+        assert_eq!(screen(&rows[2]).len(), 3);
+    }
+
+    // Whether any SGR sequence sets a color
+    fn colored(screen: &[u8]) -> bool {
+        let screen = String::from_utf8_lossy(screen);
+        screen.split("\x1b[").skip(1).filter_map(|part| part.find(|c: char| c.is_ascii_alphabetic()).map(|end| &part[..=end]))
+            .filter_map(|sequence| sequence.strip_suffix('m'))
+            .any(|params| params.split(';').filter_map(|param| param.parse::<u8>().ok()).any(|n| matches!(n, 30..=49 | 90..=107)))
+    }
+
+    #[test]
+    fn no_colors() {
+        let frame = |color: bool| {
+            let mut editors = vec![numbers(20)];
+            let editor = &mut editors[0];
+            editor.options.minimap = true;
+            editor.options.max_line_length = 1;
+            editor.set_cursor(2, 0);
+            editor.key(Key::Char('x'), 8);
+            editor.key(Key::Shift(Nav::Down), 8);
+            editor.goto_byte(30);
+            editor.hunks = vec![(5, 1, Mark::Added), (6, 1, Mark::Changed)];
+            let mut renderer = TermRenderer::sized(color, 40, 12);
+            let mut screen = Vec::new();
+            renderer.update(&editors, None, &mut screen, true);
+            screen
+        };
+        assert!(colored(&frame(true)));
+        let screen = frame(false);
+        assert!(!colored(&screen));
+        assert!(!inverted(&screen).is_empty());
+    }
+
+    // Rows of the screen, from 1, that a frame goes to the start of
+    fn rows(screen: &[u8]) -> Vec<usize> {
+        let screen = String::from_utf8_lossy(screen);
+        screen.split("\x1b[").filter_map(|part| part.split_once(";1H")).filter_map(|(row, _)| row.parse().ok()).collect()
+    }
+
+    #[test]
+    fn only_changed_rows_are_written() {
+        let mut editors = vec![numbers(20)];
+        editors[0].options.gutter_marks = false;
+        editors[0].set_cursor(3, 0);
+        let mut renderer = TermRenderer::sized(false, 40, 10);
+        let mut screen = Vec::new();
+        renderer.update(&editors, None, &mut screen, true);
+        assert_eq!(rows(&screen), (1..=10).collect::<Vec<usize>>());
+
+        // The same frame drawn again writes none of its rows
+        renderer.drawn = None;
+        let mut screen = Vec::new();
+        renderer.update(&editors, None, &mut screen, true);
+        assert!(screen.is_empty());
+
+        // An edit writes its line and the status row
+        editors[0].key(Key::Char('x'), 8);
+        let mut screen = Vec::new();
+        renderer.update(&editors, None, &mut screen, true);
+        assert_eq!(rows(&screen), vec![4, 10]);
+
+        // Moving writes the rows left and entered
+        editors[0].key(Key::Down, 8);
+        let mut screen = Vec::new();
+        renderer.update(&editors, None, &mut screen, true);
+        assert_eq!(rows(&screen), vec![4, 5, 10]);
+    }
+
+    // Frames of a buffer of one line of 10MB take less than a frame at 60 Hz wherever the
+    // view is on it. Only the cells left of the view are counted up to it, a chunk at a time.
+    // Timed, so left out unless asked for with `cargo test --release -- --ignored`.
+    #[test]
+    #[ignore]
+    fn long_lines_draw_in_time() {
+        let mut editors = vec![Editor::new(Rope::from_str(&"x".repeat(10_000_000)))];
+        for col in [0, 5_000_000, 10_000_000] {
+            editors[0].set_cursor(0, col);
+            let mut renderer = TermRenderer::sized(false, 200, 50);
+            let start = std::time::Instant::now();
+            for _ in 0..20 {
+                renderer.drawn = None;
+                renderer.frame.clear();
+                renderer.update(&editors, None, &mut Vec::new(), true);
+            }
+            let frame = start.elapsed() / 20;
+            assert!(frame < std::time::Duration::from_millis(16), "{:?} a frame at {}", frame, col);
+        }
+    }
+
+    #[test]
+    fn panes_past_the_end_of_a_shortened_buffer() {
+        let mut editors = vec![numbers(60)];
+        let mut renderer = TermRenderer::sized(false, 40, 14);
+        assert!(renderer.split(Layout::Stacked));
+        renderer.panes[0].y = 55;
+        renderer.cycle();
+        editors[0].key(Key::End, 1);
+        editors[0].key(Key::Shift(Nav::Home), 1);
+        editors[0].key(Key::Backspace, 1);
+        renderer.update(&editors, None, &mut Vec::new(), true);
+        assert_eq!(renderer.panes[0].y, 0);
+
+        // A press into the unfocused pane lands on the one line left
+        renderer.panes[0].y = 55;
+        let press = MouseEvent::Press(MouseButton::Left, 5, 2);
+        assert_eq!(renderer.mouse(&editors, press), Some((0, 0)));
+        assert_eq!(renderer.focus, 0);
+    }
+
+    #[test]
+    fn drags_stay_within_the_pane() {
+        let editors = vec![numbers(20)];
+        let mut renderer = TermRenderer::sized(false, 40, 10);
+        renderer.update(&editors, None, &mut Vec::new(), true);
+        let spanned = renderer.spanned(&editors, 6, 100, 100);
+        assert_eq!(spanned, vec![(6, 1), (7, 1), (8, 1)]);
+        assert_eq!(renderer.spanned(&editors, 2, 1, 1), vec![(0, 0), (1, 0), (2, 0)]);
+        assert_eq!(renderer.mouse(&editors, MouseEvent::Hold(100, 100)), Some((8, 1)));
+    }
+}